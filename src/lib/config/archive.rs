@@ -0,0 +1,72 @@
+use crate::config::manifest::{Manifest, ManifestEntry};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+use tar::{Archive, Builder};
+
+/// Name of the archive written into `backup_path` by `StorageMode::Archive`.
+pub const ARCHIVE_NAME: &str = "snapshot.tar.zst";
+
+/**
+# Usecase
+Packs the given `files` (relative to `src`) into a zstd compressed tarball at `dst`.
+Entries are stored relative to `src`, so restoring never depends on where the save lived.
+Returns the size of the finished archive in bytes.
+*/
+pub fn write_archive(src: &Path, files: &[PathBuf], dst: &Path, level: i32) -> io::Result<u64> {
+    let encoder = zstd::Encoder::new(BufWriter::new(File::create(dst)?), level)?;
+    let mut builder = Builder::new(encoder);
+    for relative in files {
+        builder.append_path_with_name(src.join(relative), relative)?;
+    }
+    builder.into_inner()?.finish()?;
+    Ok(dst.metadata()?.len())
+}
+
+/// Streams `archive` straight into `dst`, overwriting files that already exist.
+pub fn extract_archive(archive: &Path, dst: &Path) -> io::Result<()> {
+    let decoder = zstd::Decoder::new(BufReader::new(File::open(archive)?))?;
+    Archive::new(decoder).unpack(dst)
+}
+
+/// Lists the files in `archive` with their uncompressed sizes, reading only the tar headers.
+pub fn list_archive(archive: &Path) -> io::Result<Vec<(PathBuf, u64)>> {
+    let decoder = zstd::Decoder::new(BufReader::new(File::open(archive)?))?;
+    let mut archive = Archive::new(decoder);
+    let mut files = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() {
+            files.push((entry.path()?.into_owned(), entry.header().size()?));
+        }
+    }
+    Ok(files)
+}
+
+/// Hashes every file in `archive` as it streams past, giving the manifest of what the archive actually holds.
+pub fn manifest(archive: &Path) -> io::Result<Manifest> {
+    let decoder = zstd::Decoder::new(BufReader::new(File::open(archive)?))?;
+    let mut archive = Archive::new(decoder);
+    let mut manifest = Manifest::default();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let modified = entry.header().mtime().ok().map(|mtime| mtime as i64);
+        let mut hasher = blake3::Hasher::new();
+        let size = io::copy(&mut entry, &mut hasher)?;
+        manifest.files.insert(
+            path,
+            ManifestEntry {
+                hash: hasher.finalize().to_hex().to_string(),
+                size,
+                modified,
+            },
+        );
+    }
+    Ok(manifest)
+}
@@ -0,0 +1,465 @@
+use crate::config::{
+    check_plain_name, manifest::Manifest, s3::S3Backend, save::Save, store::Store, walk_files,
+};
+use crate::settings::{BackendConfig, Settings, StorageMode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+use ulid::Ulid;
+
+/// The two kinds of object a backend holds. Blobs are content-addressed, manifests are named by snapshot ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Blob,
+    Manifest,
+}
+
+impl ObjectKind {
+    pub fn dir(self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blobs",
+            ObjectKind::Manifest => "manifests",
+        }
+    }
+}
+
+/**
+# Usecase
+Somewhere snapshots can be kept besides `save_base_path`, on this machine or off it.
+Keys are plain names: blob keys are hex hashes of their contents, manifest keys are snapshot IDs.
+`get` fails with `io::ErrorKind::NotFound` for a key that is not there, and `delete` of such a key succeeds.
+*/
+pub trait StorageBackend {
+    fn put(&self, kind: ObjectKind, key: &str, data: &[u8]) -> io::Result<()>;
+    /// Like `put`, for the contents of the file at `path`, which backends stream instead of reading it whole.
+    /// Returns the number of bytes stored.
+    fn put_file(&self, kind: ObjectKind, key: &str, path: &Path) -> io::Result<u64> {
+        let data = fs::read(path)?;
+        self.put(kind, key, &data)?;
+        Ok(data.len() as u64)
+    }
+    fn get(&self, kind: ObjectKind, key: &str) -> io::Result<Vec<u8>>;
+    fn list(&self, kind: ObjectKind) -> io::Result<Vec<String>>;
+    fn delete(&self, kind: ObjectKind, key: &str) -> io::Result<()>;
+
+    fn contains(&self, kind: ObjectKind, key: &str) -> io::Result<bool> {
+        match self.get(kind, key) {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Rejects keys that could point outside the backend.
+pub(crate) fn check_key(kind: ObjectKind, key: &str) -> io::Result<()> {
+    check_plain_name(key)?;
+    match kind {
+        ObjectKind::Blob if key.len() < 2 || !key.is_char_boundary(2) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not a valid blob key", key),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/**
+# Usecase
+A backend in a local directory, e.g. a second disk or a mounted NAS share to mirror snapshots to.
+Laid out like the blob store: `blobs/<first two chars>/<key>` and `manifests/<key>`.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalBackend {
+    pub root: PathBuf,
+}
+
+impl LocalBackend {
+    /// Writes an object from `reader`, next to its final name and renamed, so a reader never sees half of one.
+    fn write_object(&self, kind: ObjectKind, key: &str, mut reader: impl Read) -> io::Result<u64> {
+        let path = self.path(kind, key)?;
+        let parent = path.parent().ok_or(io::ErrorKind::InvalidInput)?;
+        fs::create_dir_all(parent)?;
+        let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+        let length = io::copy(&mut reader, &mut tmp)?;
+        tmp.as_file().sync_all()?;
+        tmp.persist(path).map_err(|err| err.error)?;
+        Ok(length)
+    }
+
+    fn path(&self, kind: ObjectKind, key: &str) -> io::Result<PathBuf> {
+        check_key(kind, key)?;
+        let dir = self.root.join(kind.dir());
+        Ok(match kind {
+            ObjectKind::Blob => dir.join(&key[..2]).join(key),
+            ObjectKind::Manifest => dir.join(key),
+        })
+    }
+}
+
+impl StorageBackend for LocalBackend {
+    fn put(&self, kind: ObjectKind, key: &str, data: &[u8]) -> io::Result<()> {
+        self.write_object(kind, key, data).map(|_| ())
+    }
+
+    fn put_file(&self, kind: ObjectKind, key: &str, path: &Path) -> io::Result<u64> {
+        self.write_object(kind, key, BufReader::new(File::open(path)?))
+    }
+
+    fn get(&self, kind: ObjectKind, key: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(kind, key)?)
+    }
+
+    fn list(&self, kind: ObjectKind) -> io::Result<Vec<String>> {
+        let dir = self.root.join(kind.dir());
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(walk_files(&dir)?
+            .iter()
+            .filter_map(|path| path.file_name()?.to_str().map(String::from))
+            // NOTE: Leftovers of an interrupted put
+            .filter(|name| !name.starts_with(".tmp"))
+            .collect())
+    }
+
+    fn delete(&self, kind: ObjectKind, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(kind, key)?) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn contains(&self, kind: ObjectKind, key: &str) -> io::Result<bool> {
+        Ok(self.path(kind, key)?.is_file())
+    }
+}
+
+/// Opens the backend described by `config`, reading S3 credentials from the environment.
+pub fn open_backend(config: &BackendConfig) -> io::Result<Box<dyn StorageBackend>> {
+    match config {
+        BackendConfig::Local { path } => Ok(Box::new(LocalBackend { root: path.clone() })),
+        BackendConfig::S3 {
+            endpoint,
+            bucket,
+            region,
+            prefix,
+            access_key_env,
+            secret_key_env,
+        } => {
+            let var = |name: &String| {
+                env::var(name).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("environment variable {} is not set", name),
+                    )
+                })
+            };
+            Ok(Box::new(S3Backend {
+                endpoint: endpoint.clone(),
+                bucket: bucket.clone(),
+                region: region.clone(),
+                prefix: prefix.clone(),
+                access_key: var(access_key_env)?,
+                secret_key: var(secret_key_env)?,
+            }))
+        }
+    }
+}
+
+/**
+# Usecase
+What a backend keeps as a snapshot's manifest: every file below its `backup_path` by the key of the blob holding
+it, and for dedup snapshots the blobs of the shared store they reference. Those are uploaded as they are on disk,
+so an encrypted store stays encrypted off-machine; `keyring` is the blob holding its wrapped key.
+*/
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteSnapshot {
+    pub files: BTreeMap<PathBuf, String>,
+    #[serde(default)]
+    pub store_blobs: BTreeSet<String>,
+    #[serde(default)]
+    pub keyring: Option<String>,
+}
+
+impl RemoteSnapshot {
+    fn keys(&self) -> impl Iterator<Item = &String> {
+        self.files
+            .values()
+            .chain(self.store_blobs.iter())
+            .chain(self.keyring.iter())
+    }
+}
+
+/// What a push or pull moved. Blobs the other side already had are `skipped`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TransferReport {
+    pub transferred: usize,
+    pub skipped: usize,
+    pub bytes: u64,
+}
+
+/// BLAKE3 of the file at `path`, read in pieces.
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+fn put_blob(
+    backend: &dyn StorageBackend,
+    key: &str,
+    path: &Path,
+    report: &mut TransferReport,
+) -> io::Result<()> {
+    if backend.contains(ObjectKind::Blob, key)? {
+        report.skipped += 1;
+        return Ok(());
+    }
+    report.bytes += backend.put_file(ObjectKind::Blob, key, path)?;
+    report.transferred += 1;
+    Ok(())
+}
+
+/**
+# Usecase
+Copies the snapshot `save` to `backend`. Only blobs the backend does not have yet are uploaded, so pushing a
+snapshot that shares most of its files with an earlier one is cheap. The manifest goes up last, so a snapshot is
+only listed once everything it needs is there.
+*/
+pub fn push_snapshot(
+    save: &Save,
+    settings: &Settings,
+    backend: &dyn StorageBackend,
+) -> io::Result<TransferReport> {
+    let mut report = TransferReport::default();
+    let mut remote = RemoteSnapshot::default();
+    if save.storage == StorageMode::Dedup {
+        let store = Store::open_with(settings)?;
+        let mut manifests = vec![store.read_manifest(&save.manifest_path())?];
+        for (_, part) in save.root_saves()? {
+            manifests.push(store.read_manifest(&part.manifest_path())?);
+        }
+        for entry in manifests
+            .iter()
+            .flat_map(|manifest| manifest.files.values())
+        {
+            if remote.store_blobs.insert(entry.hash.clone()) {
+                put_blob(
+                    backend,
+                    &entry.hash,
+                    &store.blob_path(&entry.hash),
+                    &mut report,
+                )?;
+            }
+        }
+        if store.is_encrypted() {
+            let key = hash_file(&store.keyring_path())?;
+            put_blob(backend, &key, &store.keyring_path(), &mut report)?;
+            remote.keyring = Some(key);
+        }
+    }
+    for relative in walk_files(&save.backup_path)? {
+        let path = save.backup_path.join(&relative);
+        let key = hash_file(&path)?;
+        put_blob(backend, &key, &path, &mut report)?;
+        remote.files.insert(relative, key);
+    }
+    let json = serde_json::to_vec(&remote).map_err(io::Error::other)?;
+    backend.put(ObjectKind::Manifest, &save.id.to_string(), &json)?;
+    Ok(report)
+}
+
+/// The manifest `backend` keeps for snapshot `id`.
+pub fn remote_snapshot(backend: &dyn StorageBackend, id: Ulid) -> io::Result<RemoteSnapshot> {
+    let json = backend.get(ObjectKind::Manifest, &id.to_string())?;
+    serde_json::from_slice(&json).map_err(io::Error::other)
+}
+
+/// Writes `data` to `path` through a temporary sibling.
+fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let parent = path.parent().ok_or(io::ErrorKind::InvalidInput)?;
+    fs::create_dir_all(parent)?;
+    let mut tmp = tempfile::NamedTempFile::new_in(parent)?;
+    io::Write::write_all(&mut tmp, data)?;
+    tmp.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// An error for `data` fetched as `key` that is not what the key says.
+fn mismatch(key: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("blob {} does not match its contents", key),
+    )
+}
+
+/**
+# Usecase
+Brings snapshot `id` back from `backend` into `backup_path`, e.g. after losing the local disk. Store blobs of a
+dedup snapshot that are missing locally are put back into the store first, and counted as referenced once the
+snapshot is in place, so deleting it later releases them like any other. Every blob is checked before it is
+written: files of the snapshot and plain store blobs against their key, encrypted store blobs by decrypting them,
+so pulling from an encrypted store needs its key. The keyring is only taken over by a store that has none, and
+a store with a different one is refused, since its key could not open the pulled blobs.
+The catalog is not touched; `rebuild_catalog` or `fsck` pick the snapshot up from its sidecar. Pulling a
+snapshot that is still there locally counts its blobs twice, which keeps them around longer but never loses one.
+*/
+pub fn pull_snapshot(
+    backend: &dyn StorageBackend,
+    id: Ulid,
+    backup_path: &Path,
+    settings: &Settings,
+) -> io::Result<TransferReport> {
+    let remote = remote_snapshot(backend, id)?;
+    let mut report = TransferReport::default();
+    let mut store = None;
+    if !remote.store_blobs.is_empty() {
+        let keyring_path = Store::open(&settings.store_path())?.keyring_path();
+        let mut written = false;
+        if let Some(key) = &remote.keyring {
+            let keyring = backend.get(ObjectKind::Blob, key)?;
+            if blake3::hash(&keyring).to_hex().as_str() != key {
+                return Err(mismatch(key));
+            }
+            match fs::read(&keyring_path) {
+                Ok(local) if local == keyring => {}
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{:?} holds a different key than the snapshot", keyring_path),
+                    ))
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    write_file(&keyring_path, &keyring)?;
+                    written = true;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let opened = match Store::open_with(settings) {
+            Ok(store) => store,
+            Err(err) => {
+                // NOTE: A keyring the configured key cannot open would lock the store for good
+                if written {
+                    fs::remove_file(&keyring_path)?;
+                }
+                return Err(err);
+            }
+        };
+        for key in &remote.store_blobs {
+            check_key(ObjectKind::Blob, key)?;
+            if opened.contains(key) {
+                report.skipped += 1;
+                continue;
+            }
+            let data = backend.get(ObjectKind::Blob, key)?;
+            if !opened.check_blob_data(key, &data)? {
+                return Err(mismatch(key));
+            }
+            write_file(&opened.blob_path(key), &data)?;
+            report.transferred += 1;
+            report.bytes += data.len() as u64;
+        }
+        store = Some(opened);
+    }
+    for (relative, key) in &remote.files {
+        if relative
+            .components()
+            .any(|component| !matches!(component, std::path::Component::Normal(_)))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} would be written outside the snapshot", relative),
+            ));
+        }
+        let data = backend.get(ObjectKind::Blob, key)?;
+        if blake3::hash(&data).to_hex().as_str() != key {
+            return Err(mismatch(key));
+        }
+        write_file(&backup_path.join(relative), &data)?;
+        report.transferred += 1;
+        report.bytes += data.len() as u64;
+    }
+    // NOTE: The pulled manifests, the snapshot's own and one per extra root, hold the store blobs it uses
+    if let Some(store) = &store {
+        for relative in remote.files.keys() {
+            if relative.file_name() == Some(OsStr::new("manifest.json")) {
+                store.retain(&store.read_manifest(&backup_path.join(relative))?)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/**
+# Usecase
+The store manifests, the snapshot's own and one per extra root, kept among the files of snapshot `id` on `backend`.
+Lets `store` release the blobs of a dedup snapshot whose local copy is gone.
+*/
+pub fn remote_manifests(
+    backend: &dyn StorageBackend,
+    id: Ulid,
+    store: &Store,
+) -> io::Result<Vec<Manifest>> {
+    let remote = remote_snapshot(backend, id)?;
+    let scratch = tempfile::tempdir()?;
+    let mut manifests = Vec::new();
+    for (relative, key) in &remote.files {
+        if relative.file_name() != Some(OsStr::new("manifest.json")) {
+            continue;
+        }
+        let data = backend.get(ObjectKind::Blob, key)?;
+        if blake3::hash(&data).to_hex().as_str() != key {
+            return Err(mismatch(key));
+        }
+        // NOTE: Sealed manifests are only read from a file, as they are written
+        let path = scratch.path().join(manifests.len().to_string());
+        fs::write(&path, &data)?;
+        manifests.push(store.read_manifest(&path)?);
+    }
+    Ok(manifests)
+}
+
+/// Removes snapshot `id` from `backend`, see `forget_snapshots`.
+pub fn forget_snapshot(backend: &dyn StorageBackend, id: Ulid) -> io::Result<usize> {
+    forget_snapshots(backend, &[id])
+}
+
+/**
+# Usecase
+Removes the snapshots `ids` from `backend`, then every blob no remaining snapshot refers to.
+Every remaining manifest is read to find those, so a prune forgets all its snapshots in one call instead of
+collecting once per snapshot. Returns the number of blobs deleted.
+
+Only one machine may write to a backend at a time. A push uploads its blobs before its manifest, so a push
+running on another machine while this collects would have blobs deleted that its manifest then points at.
+Machines sharing a bucket or drive each need their own `prefix` or directory in it.
+*/
+pub fn forget_snapshots(backend: &dyn StorageBackend, ids: &[Ulid]) -> io::Result<usize> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    for id in ids {
+        backend.delete(ObjectKind::Manifest, &id.to_string())?;
+    }
+    let mut referenced = BTreeSet::new();
+    for key in backend.list(ObjectKind::Manifest)? {
+        let json = backend.get(ObjectKind::Manifest, &key)?;
+        let remote: RemoteSnapshot = serde_json::from_slice(&json).map_err(io::Error::other)?;
+        referenced.extend(remote.keys().cloned());
+    }
+    let mut removed = 0;
+    for key in backend.list(ObjectKind::Blob)? {
+        if !referenced.contains(&key) {
+            backend.delete(ObjectKind::Blob, &key)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,151 @@
+use crate::config::walk_files;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// How a single file ended up being copied, fastest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyStrategy {
+    /// FICLONE: the copy shares extents with the source and takes no extra space (btrfs, XFS).
+    Reflink,
+    /// `copy_file_range`: the kernel copies the data without it passing through userspace.
+    CopyFileRange,
+    /// Plain read/write loop, works everywhere.
+    ByteCopy,
+}
+
+/// Tally of strategies used while copying a tree.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CopyReport {
+    pub reflinked: usize,
+    pub copy_file_range: usize,
+    pub byte_copied: usize,
+    pub bytes: u64,
+}
+
+impl CopyReport {
+    pub fn record(&mut self, strategy: CopyStrategy, bytes: u64) {
+        match strategy {
+            CopyStrategy::Reflink => self.reflinked += 1,
+            CopyStrategy::CopyFileRange => self.copy_file_range += 1,
+            CopyStrategy::ByteCopy => self.byte_copied += 1,
+        }
+        self.bytes += bytes;
+    }
+
+    pub fn files(&self) -> usize {
+        self.reflinked + self.copy_file_range + self.byte_copied
+    }
+}
+
+impl fmt::Display for CopyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} reflinked, {} via copy_file_range, {} byte copied",
+            self.reflinked, self.copy_file_range, self.byte_copied
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(src: &File, dst: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    // SAFETY: both descriptors are open for the duration of the call
+    match unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn copy_range(src: &File, dst: &File, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(1 << 30) as usize;
+        // SAFETY: null offsets make the kernel use and advance both file positions
+        let copied = unsafe {
+            libc::copy_file_range(
+                src.as_raw_fd(),
+                std::ptr::null_mut(),
+                dst.as_raw_fd(),
+                std::ptr::null_mut(),
+                chunk,
+                0,
+            )
+        };
+        match copied {
+            -1 => return Err(io::Error::last_os_error()),
+            // NOTE: The file shrank while we were copying it
+            0 => break,
+            copied => remaining -= copied as u64,
+        }
+    }
+    Ok(())
+}
+
+/**
+# Usecase
+Copies `src` to `dst`, trying a reflink first, then `copy_file_range`, and only then a plain byte copy.
+Permissions and the modification time are carried over, like `cp -p`. Returns the strategy that worked.
+*/
+pub fn copy_file(src: &Path, dst: &Path) -> io::Result<CopyStrategy> {
+    let mut source = File::open(src)?;
+    let metadata = source.metadata()?;
+    let mut target = File::create(dst)?;
+    let strategy = fast_copy(&source, &target, metadata.len());
+    let strategy = match strategy {
+        Some(strategy) => strategy,
+        None => {
+            // NOTE: A failed fast path may have written part of the file, so start over
+            target.set_len(0)?;
+            target.seek(SeekFrom::Start(0))?;
+            source.seek(SeekFrom::Start(0))?;
+            io::copy(&mut source, &mut target)?;
+            CopyStrategy::ByteCopy
+        }
+    };
+    target.set_modified(metadata.modified()?)?;
+    fs::set_permissions(dst, metadata.permissions())?;
+    Ok(strategy)
+}
+
+#[cfg(target_os = "linux")]
+fn fast_copy(source: &File, target: &File, len: u64) -> Option<CopyStrategy> {
+    if reflink(source, target).is_ok() {
+        return Some(CopyStrategy::Reflink);
+    }
+    match copy_range(source, target, len) {
+        Ok(()) => Some(CopyStrategy::CopyFileRange),
+        Err(_) => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fast_copy(_source: &File, _target: &File, _len: u64) -> Option<CopyStrategy> {
+    None
+}
+
+/// Copies every file below `src` into `dst`, creating directories as needed and overwriting existing files.
+pub fn copy_tree(src: &Path, dst: &Path) -> io::Result<CopyReport> {
+    copy_files(src, &walk_files(src)?, dst)
+}
+
+/// Copies the given `files` (relative to `src`) into `dst`.
+pub fn copy_files(src: &Path, files: &[PathBuf], dst: &Path) -> io::Result<CopyReport> {
+    let mut report = CopyReport::default();
+    fs::create_dir_all(dst)?;
+    for relative in files {
+        let target = dst.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let strategy = copy_file(&src.join(relative), &target)?;
+        report.record(strategy, target.metadata()?.len());
+    }
+    Ok(report)
+}
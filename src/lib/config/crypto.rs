@@ -0,0 +1,297 @@
+use crate::settings::KeySource;
+use chacha20poly1305::{
+    aead::{
+        rand_core::RngCore,
+        stream::{DecryptorBE32, EncryptorBE32},
+        Aead, AeadCore, KeyInit, OsRng, Payload,
+    },
+    Key, XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+/// Every encrypted blob or manifest starts with these bytes, so plain and sealed data can be told apart.
+pub const MAGIC: &[u8; 8] = b"OXIENC01";
+const CHUNK: usize = 64 * 1024;
+const TAG: usize = 16;
+const STREAM_NONCE: usize = 19;
+const NONCE: usize = 24;
+
+fn tampered() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "authentication failed: the data was modified or the key is wrong",
+    )
+}
+
+/// Reads until `buffer` is full or the reader is exhausted, returning how much was read.
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/**
+# Usecase
+The data encryption key of a store. It never changes once created: only its wrapping in the
+`Keyring` does, which is why changing a passphrase does not have to touch a single blob.
+*/
+pub struct Cipher {
+    key: Key,
+}
+
+impl Cipher {
+    pub fn generate() -> Cipher {
+        Cipher {
+            key: XChaCha20Poly1305::generate_key(&mut OsRng),
+        }
+    }
+
+    /// Key for the keyed blob hashes, so blob names do not reveal the hash of the plaintext.
+    pub fn id_key(&self) -> [u8; 32] {
+        blake3::derive_key("oxidized_saves blob id v1", &self.key)
+    }
+
+    /// Encrypts `reader` into `writer` in 64KiB chunks, each authenticated together with `aad`.
+    pub fn encrypt_stream<R: Read, W: Write>(
+        &self,
+        mut reader: R,
+        mut writer: W,
+        aad: &[u8],
+    ) -> io::Result<()> {
+        let mut nonce = [0u8; STREAM_NONCE];
+        OsRng.fill_bytes(&mut nonce);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&nonce)?;
+        let mut encryptor = EncryptorBE32::<XChaCha20Poly1305>::new(&self.key, &nonce.into());
+        let mut current = vec![0u8; CHUNK];
+        let mut next = vec![0u8; CHUNK];
+        let mut current_len = read_full(&mut reader, &mut current)?;
+        loop {
+            let next_len = read_full(&mut reader, &mut next)?;
+            let payload = Payload {
+                msg: &current[..current_len],
+                aad,
+            };
+            if next_len == 0 {
+                let sealed = encryptor.encrypt_last(payload).map_err(|_| tampered())?;
+                writer.write_all(&sealed)?;
+                return writer.flush();
+            }
+            let sealed = encryptor.encrypt_next(payload).map_err(|_| tampered())?;
+            writer.write_all(&sealed)?;
+            std::mem::swap(&mut current, &mut next);
+            current_len = next_len;
+        }
+    }
+
+    /// Reverses `encrypt_stream`, failing with `InvalidData` if any chunk was tampered with.
+    pub fn decrypt_stream<R: Read, W: Write>(
+        &self,
+        mut reader: R,
+        mut writer: W,
+        aad: &[u8],
+    ) -> io::Result<()> {
+        let mut header = [0u8; 8 + STREAM_NONCE];
+        if read_full(&mut reader, &mut header)? != header.len() || !is_encrypted(&header) {
+            return Err(tampered());
+        }
+        let nonce: [u8; STREAM_NONCE] = header[8..].try_into().map_err(|_| tampered())?;
+        let mut decryptor = DecryptorBE32::<XChaCha20Poly1305>::new(&self.key, &nonce.into());
+        let mut current = vec![0u8; CHUNK + TAG];
+        let mut next = vec![0u8; CHUNK + TAG];
+        let mut current_len = read_full(&mut reader, &mut current)?;
+        loop {
+            let next_len = read_full(&mut reader, &mut next)?;
+            let payload = Payload {
+                msg: &current[..current_len],
+                aad,
+            };
+            if next_len == 0 {
+                let plain = decryptor.decrypt_last(payload).map_err(|_| tampered())?;
+                writer.write_all(&plain)?;
+                return writer.flush();
+            }
+            let plain = decryptor.decrypt_next(payload).map_err(|_| tampered())?;
+            writer.write_all(&plain)?;
+            std::mem::swap(&mut current, &mut next);
+            current_len = next_len;
+        }
+    }
+
+    /// Encrypts a small in-memory value such as a manifest.
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        seal_with(&self.key, plaintext, aad)
+    }
+
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        open_with(&self.key, sealed, aad)
+    }
+}
+
+fn seal_with(key: &Key, plaintext: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = XChaCha20Poly1305::new(key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| tampered())?;
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE + sealed.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+fn open_with(key: &Key, sealed: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+    if sealed.len() < MAGIC.len() + NONCE || !is_encrypted(sealed) {
+        return Err(tampered());
+    }
+    let (nonce, ciphertext) = sealed[MAGIC.len()..].split_at(NONCE);
+    XChaCha20Poly1305::new(key)
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| tampered())
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kdf {
+    Argon2id,
+    KeyFile,
+}
+
+/// Argon2id costs a passphrase was derived with. Kept in the keyring, so it still unlocks if the argon2 crate's
+/// defaults change.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Argon2Params {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for Argon2Params {
+    /// The defaults of argon2 0.5, which keyrings without stored parameters were written with.
+    fn default() -> Argon2Params {
+        Argon2Params {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+/**
+# Usecase
+Holds the store's data key, encrypted ("wrapped") with a key derived from the user's passphrase or key file.
+Stored as `keyring.json` in the root of the store.
+*/
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Keyring {
+    pub kdf: Kdf,
+    pub salt: String,
+    pub wrapped_key: String,
+    /// Set for `Kdf::Argon2id`. Keyrings from before it was stored use `Argon2Params::default`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argon2: Option<Argon2Params>,
+}
+
+impl Keyring {
+    /// Wraps `cipher`'s key with a fresh salt for the given `source`.
+    pub fn wrap(source: &KeySource, cipher: &Cipher) -> io::Result<Keyring> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let (kdf, argon2) = match source {
+            KeySource::KeyFile(_) => (Kdf::KeyFile, None),
+            KeySource::Passphrase(_) | KeySource::PassphraseEnv(_) => {
+                (Kdf::Argon2id, Some(Argon2Params::default()))
+            }
+        };
+        let kek = derive_kek(source, kdf, argon2.unwrap_or_default(), &salt)?;
+        Ok(Keyring {
+            kdf,
+            salt: hex::encode(salt),
+            wrapped_key: hex::encode(seal_with(&kek, &cipher.key, b"keyring")?),
+            argon2,
+        })
+    }
+
+    pub fn unwrap(&self, source: &KeySource) -> io::Result<Cipher> {
+        let salt = hex::decode(&self.salt).map_err(io::Error::other)?;
+        let wrapped = hex::decode(&self.wrapped_key).map_err(io::Error::other)?;
+        let kek = derive_kek(source, self.kdf, self.argon2.unwrap_or_default(), &salt)?;
+        let key = open_with(&kek, &wrapped, b"keyring")?;
+        Ok(Cipher {
+            key: *Key::from_slice(&key),
+        })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Keyring> {
+        serde_json::from_reader(File::open(path)?).map_err(io::Error::other)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(
+            path,
+            serde_json::to_vec_pretty(self).map_err(io::Error::other)?,
+        )
+    }
+}
+
+fn derive_kek(source: &KeySource, kdf: Kdf, argon2: Argon2Params, salt: &[u8]) -> io::Result<Key> {
+    let mut kek = Key::default();
+    match (kdf, source) {
+        (Kdf::KeyFile, KeySource::KeyFile(path)) => {
+            let material = fs::read(path)?;
+            if material.len() < 32 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "key files must hold at least 32 bytes",
+                ));
+            }
+            let mut hasher = blake3::Hasher::new_derive_key("oxidized_saves key file v1");
+            hasher.update(salt);
+            hasher.update(&material);
+            kek.copy_from_slice(hasher.finalize().as_bytes());
+        }
+        (Kdf::Argon2id, KeySource::Passphrase(_) | KeySource::PassphraseEnv(_)) => {
+            let passphrase = source.passphrase()?;
+            let invalid =
+                |err: argon2::Error| io::Error::new(io::ErrorKind::InvalidInput, err.to_string());
+            let params = argon2::Params::new(argon2.m_cost, argon2.t_cost, argon2.p_cost, None)
+                .map_err(invalid)?;
+            argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                .hash_password_into(passphrase.as_bytes(), salt, &mut kek)
+                .map_err(invalid)?;
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the configured key source does not match the store's keyring",
+            ))
+        }
+    }
+    Ok(kek)
+}
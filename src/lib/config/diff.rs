@@ -0,0 +1,257 @@
+use crate::config::manifest::{Manifest, ManifestEntry};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+/// How much of a file is looked at to decide whether it is text. Same heuristic git uses.
+const SNIFF_LEN: usize = 8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// What changed inside a modified file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentSummary {
+    /// Lines that occur more often in one version than in the other, regardless of where they moved.
+    Text {
+        lines_added: usize,
+        lines_removed: usize,
+    },
+    /// Bytes that differ at the same offset, plus any difference in length.
+    Binary {
+        bytes_changed: u64,
+        first_difference: Option<u64>,
+    },
+}
+
+/// One file that differs between two manifests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub path: PathBuf,
+    pub change: Change,
+    pub old: Option<ManifestEntry>,
+    pub new: Option<ManifestEntry>,
+    /// Only filled in by `SnapshotDiff::summarize_content`.
+    pub content: Option<ContentSummary>,
+}
+
+impl FileDiff {
+    pub fn size_delta(&self) -> i64 {
+        let size =
+            |entry: &Option<ManifestEntry>| entry.as_ref().map_or(0, |entry| entry.size as i64);
+        size(&self.new) - size(&self.old)
+    }
+
+    /// Seconds between the two modification times, when both sides know theirs.
+    pub fn mtime_delta(&self) -> Option<i64> {
+        let old = self.old.as_ref()?.modified?;
+        let new = self.new.as_ref()?.modified?;
+        Some(new - old)
+    }
+}
+
+/**
+# Usecase
+The difference between two snapshots, or a snapshot and the live save, built from their manifests.
+Files are only hashed once, when the manifests are made, so the diff itself never touches the files.
+
+# Example
+```
+use oxi::config::diff::{Change, SnapshotDiff};
+use oxi::config::manifest::{Manifest, ManifestEntry};
+use std::path::PathBuf;
+
+let entry = |hash: &str, size| ManifestEntry { hash: hash.to_string(), size, modified: None };
+let mut old = Manifest::default();
+old.files.insert(PathBuf::from("slot_1.sav"), entry("aa", 10));
+old.files.insert(PathBuf::from("slot_2.sav"), entry("bb", 10));
+let mut new = Manifest::default();
+new.files.insert(PathBuf::from("slot_1.sav"), entry("cc", 12));
+new.files.insert(PathBuf::from("slot_3.sav"), entry("dd", 10));
+
+let diff = SnapshotDiff::between(&old, &new);
+let changes: Vec<Change> = diff.files.iter().map(|file| file.change).collect();
+assert_eq!(changes, vec![Change::Modified, Change::Removed, Change::Added]);
+assert_eq!(diff.files[0].size_delta(), 2);
+```
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    /// Sorted by path.
+    pub files: Vec<FileDiff>,
+}
+
+impl SnapshotDiff {
+    pub fn between(old: &Manifest, new: &Manifest) -> SnapshotDiff {
+        let mut files = Vec::new();
+        for (path, entry) in &old.files {
+            let change = match new.files.get(path) {
+                None => Change::Removed,
+                Some(found) if found.hash != entry.hash || found.size != entry.size => {
+                    Change::Modified
+                }
+                Some(_) => continue,
+            };
+            files.push(FileDiff {
+                path: path.clone(),
+                change,
+                old: Some(entry.clone()),
+                new: new.files.get(path).cloned(),
+                content: None,
+            });
+        }
+        files.extend(
+            new.files
+                .iter()
+                .filter(|(path, _)| !old.files.contains_key(*path))
+                .map(|(path, entry)| FileDiff {
+                    path: path.clone(),
+                    change: Change::Added,
+                    old: None,
+                    new: Some(entry.clone()),
+                    content: None,
+                }),
+        );
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        SnapshotDiff { files }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn count(&self, change: Change) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.change == change)
+            .count()
+    }
+
+    /// Fills in `content` for every modified file. `old` and `new` map a manifest path to the file on disk.
+    pub fn summarize_content(
+        &mut self,
+        old: impl Fn(&Path) -> PathBuf,
+        new: impl Fn(&Path) -> PathBuf,
+    ) -> io::Result<()> {
+        for file in &mut self.files {
+            if file.change == Change::Modified {
+                file.content = Some(summarize(&old(&file.path), &new(&file.path))?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in &self.files {
+            let (color, sign) = match file.change {
+                Change::Added => ("\x1b[32m", '+'),
+                Change::Removed => ("\x1b[31m", '-'),
+                Change::Modified => ("\x1b[33m", '~'),
+            };
+            write!(
+                f,
+                "{}{} {}\x1b[0m ({:+} bytes",
+                color,
+                sign,
+                file.path.display(),
+                file.size_delta()
+            )?;
+            if let Some(delta) = file.mtime_delta() {
+                write!(f, ", mtime {:+}s", delta)?;
+            }
+            match file.content {
+                Some(ContentSummary::Text {
+                    lines_added,
+                    lines_removed,
+                }) => write!(
+                    f,
+                    ", {} lines added, {} removed",
+                    lines_added, lines_removed
+                )?,
+                Some(ContentSummary::Binary {
+                    bytes_changed,
+                    first_difference,
+                }) => {
+                    write!(f, ", binary, {} bytes changed", bytes_changed)?;
+                    if let Some(offset) = first_difference {
+                        write!(f, " from offset {:#x}", offset)?;
+                    }
+                }
+                None => {}
+            }
+            writeln!(f, ")")?;
+        }
+        write!(
+            f,
+            "\x1b[32m{} added\x1b[0m, \x1b[31m{} removed\x1b[0m, \x1b[33m{} modified\x1b[0m",
+            self.count(Change::Added),
+            self.count(Change::Removed),
+            self.count(Change::Modified)
+        )
+    }
+}
+
+fn looks_binary(path: &Path) -> io::Result<bool> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(head.contains(&0))
+}
+
+fn line_counts(text: &str) -> HashMap<&str, usize> {
+    let mut counts = HashMap::new();
+    for line in text.lines() {
+        *counts.entry(line).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Summarizes how `new` differs from `old`: by lines if both look like text, by bytes otherwise.
+pub fn summarize(old: &Path, new: &Path) -> io::Result<ContentSummary> {
+    if !looks_binary(old)? && !looks_binary(new)? {
+        if let (Ok(old_text), Ok(new_text)) =
+            (std::fs::read_to_string(old), std::fs::read_to_string(new))
+        {
+            let (old_lines, new_lines) = (line_counts(&old_text), line_counts(&new_text));
+            let surplus = |from: &HashMap<&str, usize>, other: &HashMap<&str, usize>| -> usize {
+                from.iter()
+                    .map(|(line, count)| count.saturating_sub(*other.get(line).unwrap_or(&0)))
+                    .sum()
+            };
+            return Ok(ContentSummary::Text {
+                lines_added: surplus(&new_lines, &old_lines),
+                lines_removed: surplus(&old_lines, &new_lines),
+            });
+        }
+    }
+    let mut old_bytes = BufReader::new(File::open(old)?).bytes();
+    let mut new_bytes = BufReader::new(File::open(new)?).bytes();
+    let (mut offset, mut bytes_changed, mut first_difference) = (0u64, 0u64, None);
+    loop {
+        match (old_bytes.next().transpose()?, new_bytes.next().transpose()?) {
+            (None, None) => break,
+            (a, b) => {
+                if a != b {
+                    bytes_changed += 1;
+                    first_difference.get_or_insert(offset);
+                }
+            }
+        }
+        offset += 1;
+    }
+    Ok(ContentSummary::Binary {
+        bytes_changed,
+        first_difference,
+    })
+}
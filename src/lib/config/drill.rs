@@ -0,0 +1,35 @@
+use crate::config::timestamps;
+use crate::config::{game::Game, verify::SaveVerification};
+use crate::settings::Settings;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+/// Outcome of the last restore drill run for a game.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DrillRecord {
+    pub id: Ulid,
+    #[serde(with = "timestamps")]
+    pub ran_at: DateTime<Utc>,
+    pub passed: bool,
+}
+
+/**
+# Usecase
+Runs a restore drill for every game that is due according to `Settings.drill_interval_days`,
+records the outcome on the game and returns the results. Does nothing if drills are not scheduled.
+*/
+pub fn run_due_drills(
+    games: &mut [Game],
+    settings: &Settings,
+    now: DateTime<Utc>,
+) -> Vec<SaveVerification> {
+    let Some(days) = settings.drill_interval_days else {
+        return Vec::new();
+    };
+    games
+        .iter_mut()
+        .filter(|game| game.drill_due(Duration::days(days.into()), now))
+        .filter_map(|game| game.drill_latest(settings, now))
+        .collect()
+}
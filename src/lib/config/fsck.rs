@@ -0,0 +1,383 @@
+use crate::config::{
+    archive,
+    game::Game,
+    manifest::Manifest,
+    mirror,
+    save::Save,
+    save_conf,
+    sidecar::{self, game_from_saves, is_reserved_dir, SIDECAR_NAME},
+    store::Store,
+    walk_files,
+};
+use crate::settings::{Settings, StorageMode};
+use chrono::Utc;
+use std::{
+    collections::BTreeSet,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use ulid::Ulid;
+
+/// Directory under `save_base_path` that quarantined snapshots are moved into, one subdirectory per run.
+pub const QUARANTINE_DIR: &str = ".quarantine";
+
+/// Something that does not add up between the catalog and `save_base_path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// In the catalog, but its `backup_path` is gone.
+    Missing {
+        game_title: String,
+        id: Ulid,
+        backup_path: PathBuf,
+    },
+    /// A directory no `Save` points at. `adoptable` when it has a sidecar to rebuild the `Save` from.
+    Unreferenced { path: PathBuf, adoptable: bool },
+    /// The snapshot holds a different number of bytes than its manifest says it should.
+    SizeMismatch {
+        game_title: String,
+        id: Ulid,
+        backup_path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+/**
+# Usecase
+What to do about a `Problem`.
+- `Adopt`: make the catalog agree with the disk. Adds an unreferenced snapshot from its sidecar, or records a new
+  manifest for a snapshot whose size changed, accepting what is there now.
+- `Remove`: drop the catalog entry and delete whatever is left on disk.
+- `Quarantine`: drop the catalog entry and move the directory below `QUARANTINE_DIR` to be looked at later.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Adopt,
+    Remove,
+    Quarantine,
+}
+
+impl Problem {
+    /// The actions `resolve` accepts for this problem.
+    pub fn actions(&self) -> &'static [Action] {
+        match self {
+            Problem::Missing { .. } => &[Action::Remove],
+            Problem::Unreferenced {
+                adoptable: false, ..
+            } => &[Action::Remove, Action::Quarantine],
+            Problem::Unreferenced { .. } | Problem::SizeMismatch { .. } => {
+                &[Action::Adopt, Action::Remove, Action::Quarantine]
+            }
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Missing {
+                game_title,
+                id,
+                backup_path,
+            } => write!(
+                f,
+                "\x1b[31mmissing\x1b[0m \x1b[34m{}\x1b[0m {}: {:?} is gone",
+                game_title, id, backup_path
+            ),
+            Problem::Unreferenced { path, adoptable } => write!(
+                f,
+                "\x1b[33munreferenced\x1b[0m {:?}{}",
+                path,
+                match adoptable {
+                    true => " (has a sidecar)",
+                    false => "",
+                }
+            ),
+            Problem::SizeMismatch {
+                game_title,
+                id,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                "\x1b[33msize mismatch\x1b[0m \x1b[34m{}\x1b[0m {}: {} bytes on disk, {} in the manifest",
+                game_title, id, actual, expected
+            ),
+        }
+    }
+}
+
+/// Bytes the manifest of `part` promises and bytes actually stored. `None` when there is nothing to compare.
+fn part_sizes(part: &Save) -> Result<Option<(u64, u64)>, io::Error> {
+    // NOTE: Dedup blobs are compressed or encrypted and shared, so their sizes say nothing; that is verify's job
+    if part.storage == StorageMode::Dedup {
+        return Ok(None);
+    }
+    let expected = match Manifest::load(&part.manifest_path()) {
+        Ok(manifest) => manifest.total_size(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let actual = match part.storage {
+        StorageMode::Archive { .. } => archive::list_archive(&part.archive_path())?
+            .iter()
+            .map(|(_, size)| size)
+            .sum(),
+        _ if !part.data_path().exists() => 0,
+        _ => {
+            let data = part.data_path();
+            walk_files(&data)?
+                .iter()
+                .map(|path| Ok(fs::metadata(data.join(path))?.len()))
+                .sum::<Result<u64, io::Error>>()?
+        }
+    };
+    Ok(Some((expected, actual)))
+}
+
+/// Expected and actual size of the whole snapshot, if they differ.
+fn size_mismatch(save: &Save) -> Result<Option<(u64, u64)>, io::Error> {
+    let mut parts = vec![save.clone()];
+    parts.extend(save.root_saves()?.into_iter().map(|(_, part)| part));
+    let mut total = (0, 0);
+    for part in &parts {
+        if let Some((expected, actual)) = part_sizes(part)? {
+            total = (total.0 + expected, total.1 + actual);
+        }
+    }
+    Ok(Some(total).filter(|(expected, actual)| expected != actual))
+}
+
+/// A directory found by `scan` that nothing in the catalog points at.
+struct Stray {
+    path: PathBuf,
+    snapshot: bool,
+}
+
+/// Whether `dir` looks like a snapshot of any storage mode.
+fn is_snapshot(dir: &Path) -> bool {
+    [SIDECAR_NAME, "manifest.json", archive::ARCHIVE_NAME]
+        .iter()
+        .any(|name| dir.join(name).is_file())
+}
+
+/**
+# Usecase
+Walks `dir` for directories nothing in `referenced` points at. Snapshots are reported one by one, so each can be
+adopted on its own. Any other directory is reported as a whole, unless it holds referenced snapshots or stray
+ones somewhere below, in which case only those strays are reported.
+*/
+fn scan(dir: &Path, referenced: &BTreeSet<PathBuf>) -> Result<Vec<Stray>, io::Error> {
+    let mut strays = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() || is_reserved_dir(&entry.file_name()) {
+            continue;
+        }
+        let path = entry.path();
+        if referenced.contains(&path) {
+            continue;
+        }
+        if is_snapshot(&path) {
+            strays.push(Stray {
+                path,
+                snapshot: true,
+            });
+            continue;
+        }
+        let inner = scan(&path, referenced)?;
+        let holds_referenced = referenced.iter().any(|known| known.starts_with(&path));
+        match holds_referenced || inner.iter().any(|stray| stray.snapshot) {
+            true => strays.extend(inner),
+            false => strays.push(Stray {
+                path,
+                snapshot: false,
+            }),
+        }
+    }
+    Ok(strays)
+}
+
+/**
+# Usecase
+Compares the catalog with `save_base_path` and lists what does not match: catalog entries whose snapshot is
+gone, directories no entry points at, and snapshots whose stored size differs from their manifest.
+The blob store, slots, quarantine and staging leftovers are left alone, see `sidecar::is_reserved_dir`. Nothing is changed; see `resolve`.
+*/
+pub fn check(games: &[Game], settings: &Settings) -> Result<Vec<Problem>, io::Error> {
+    let mut problems = Vec::new();
+    let mut referenced = BTreeSet::new();
+    for game in games {
+        for save in game.saves.iter().flatten() {
+            referenced.insert(save.backup_path.clone());
+            if !save.backup_path.exists() {
+                problems.push(Problem::Missing {
+                    game_title: game.game_title.clone(),
+                    id: save.id,
+                    backup_path: save.backup_path.clone(),
+                });
+                continue;
+            }
+            match size_mismatch(save) {
+                Ok(Some((expected, actual))) => problems.push(Problem::SizeMismatch {
+                    game_title: game.game_title.clone(),
+                    id: save.id,
+                    backup_path: save.backup_path.clone(),
+                    expected,
+                    actual,
+                }),
+                Ok(None) => {}
+                // NOTE: An unreadable manifest is for verify to report, it should not stop the rest of the check
+                Err(err) => eprintln!("Could not size {:?} due to {}", save.backup_path, err),
+            }
+        }
+    }
+    if settings.save_base_path.is_dir() {
+        problems.extend(
+            scan(&settings.save_base_path, &referenced)?
+                .into_iter()
+                .map(|stray| Problem::Unreferenced {
+                    adoptable: stray.path.join(SIDECAR_NAME).is_file(),
+                    path: stray.path,
+                }),
+        );
+    }
+    Ok(problems)
+}
+
+/// Moves `path` below `QUARANTINE_DIR`, keeping where it was relative to `save_base_path`.
+fn quarantine(path: &Path, settings: &Settings) -> Result<PathBuf, io::Error> {
+    let relative = match path.strip_prefix(&settings.save_base_path) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => PathBuf::from(path.file_name().ok_or(io::ErrorKind::InvalidInput)?),
+    };
+    let target = settings
+        .save_base_path
+        .join(QUARANTINE_DIR)
+        .join(Utc::now().format("%Y-%m-%dT%H-%M-%SZ").to_string())
+        .join(relative);
+    fs::create_dir_all(target.parent().ok_or(io::ErrorKind::InvalidInput)?)?;
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+/// Takes the snapshot `id` out of whichever game has it.
+fn take_save(games: &mut [Game], id: Ulid) -> Result<Save, io::Error> {
+    games
+        .iter_mut()
+        .find_map(|game| game.take_saves(&[id]).pop())
+        .ok_or(io::ErrorKind::NotFound.into())
+}
+
+/// Adds the snapshot in `path` to the catalog from its sidecar, to the game it belongs to or a new one.
+fn adopt(games: &mut Vec<Game>, settings: &Settings, path: &Path) -> Result<(), io::Error> {
+    let mut save = sidecar::read(path, &mut None, settings)?.save;
+    save.backup_path = path.to_path_buf();
+    if games
+        .iter()
+        .flat_map(|game| game.saves.iter().flatten())
+        .any(|known| known.id == save.id)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("snapshot {} is already in the catalog", save.id),
+        ));
+    }
+    let owner = games.iter_mut().find(|game| match save.game_id {
+        0 => game.game_title == save.parent_game,
+        game_id => game.game_id == game_id,
+    });
+    match owner {
+        Some(game) => {
+            save.parent_game = game.game_title.clone();
+            let saves = game.saves.get_or_insert_with(Vec::new);
+            saves.push(save);
+            saves.sort_by_key(|save| save.id);
+        }
+        None => games.extend(game_from_saves(vec![save])),
+    }
+    Ok(())
+}
+
+/**
+# Usecase
+Releases the store blobs of a dedup snapshot whose directory is gone, as `Save::delete` does, through the manifests
+a mirror destination still holds. Without any, its blobs stay counted: kept longer than needed, but never lost.
+*/
+fn release_missing(save: &Save, settings: &Settings) -> Result<(), io::Error> {
+    if save.storage != StorageMode::Dedup {
+        return Ok(());
+    }
+    let store = Store::open_with(settings)?;
+    match mirror::find_manifests(save, settings, &store) {
+        Some(manifests) => {
+            for manifest in &manifests {
+                store.release(manifest)?;
+            }
+        }
+        None => eprintln!(
+            "\x1b[33mNo destination holds the manifest of {} {}, its blobs stay in the store\x1b[0m",
+            save.parent_game, save.id
+        ),
+    }
+    Ok(())
+}
+
+/**
+# Usecase
+Applies `action` to `problem`. Like `retention::prune`, the catalog at `conf_path` is written before anything is
+deleted or moved, so a failure can at worst leave an unreferenced directory behind for the next check.
+Removing an unreferenced dedup snapshot leaves the blobs it used in the store, since there is no telling whether
+its references were ever counted.
+*/
+pub fn resolve(
+    games: &mut Vec<Game>,
+    settings: &Settings,
+    conf_path: &Path,
+    problem: &Problem,
+    action: Action,
+) -> Result<(), io::Error> {
+    if !problem.actions().contains(&action) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} does not apply to {}", action, problem),
+        ));
+    }
+    match (problem, action) {
+        (Problem::Missing { id, .. }, _) => {
+            let save = take_save(games, *id)?;
+            save_conf(games, conf_path)?;
+            release_missing(&save, settings)
+        }
+        (Problem::Unreferenced { path, .. }, Action::Adopt) => {
+            adopt(games, settings, path)?;
+            save_conf(games, conf_path)
+        }
+        (Problem::Unreferenced { path, .. }, Action::Remove) => fs::remove_dir_all(path),
+        (Problem::Unreferenced { path, .. }, Action::Quarantine) => {
+            quarantine(path, settings).map(|_| ())
+        }
+        (Problem::SizeMismatch { id, .. }, Action::Adopt) => {
+            let save = games
+                .iter()
+                .find_map(|game| game.find_save(*id).ok())
+                .ok_or(io::ErrorKind::NotFound)?;
+            save.record_manifest()?;
+            for (_, part) in save.root_saves()? {
+                part.record_manifest()?;
+            }
+            sidecar::write(save, settings)
+        }
+        (Problem::SizeMismatch { id, .. }, Action::Remove) => {
+            let save = take_save(games, *id)?;
+            save_conf(games, conf_path)?;
+            save.delete(settings)
+        }
+        (Problem::SizeMismatch { id, .. }, Action::Quarantine) => {
+            let save = take_save(games, *id)?;
+            save_conf(games, conf_path)?;
+            quarantine(&save.backup_path, settings).map(|_| ())
+        }
+    }
+}
@@ -1,12 +1,28 @@
+use crate::config::diff::SnapshotDiff;
+use crate::config::drill::DrillRecord;
 use crate::config::gen_home;
-use crate::config::save::Save;
+use crate::config::mirror;
+use crate::config::restore::RestoreEvent;
+use crate::config::retention::{select_kept, Candidate, PruneReport};
+use crate::config::rules::{Rules, Selection};
+use crate::config::save::{Save, SaveRoot};
+use crate::config::search::SaveQuery;
+use crate::config::sidecar::{self, SIDECAR_NAME};
+use crate::config::slots::{Slot, SLOTS_DIR};
 use crate::config::steam;
-use chrono::Local;
+use crate::config::store::Store;
+use crate::config::template::{self, TemplateValues};
+use crate::config::verify::SaveVerification;
+use crate::settings::{RetentionPolicy, Settings, StorageMode};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
+use ulid::Ulid;
 
 #[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +35,29 @@ pub struct Game {
     pub developer: Option<String>,
     pub saves: Option<Vec<Save>>,
     pub thumbnail: Vec<PathBuf>,
+    /// Overrides `Settings.storage` for this game when set.
+    #[serde(default)]
+    pub storage: Option<StorageMode>,
+    /// Restores done through `restore_save`, oldest first.
+    #[serde(default)]
+    pub restores: Vec<RestoreEvent>,
+    /// Overrides `Settings.delete_on_restore` for this game when set.
+    #[serde(default)]
+    pub delete_on_restore: Option<bool>,
+    #[serde(default)]
+    pub last_drill: Option<DrillRecord>,
+    /// Smart mode: which files in the save directory to back up. Falls back to `Rules::defaults_for`.
+    #[serde(default)]
+    pub rules: Option<Rules>,
+    /// Further named locations captured alongside the save directory in every snapshot.
+    #[serde(default)]
+    pub roots: Vec<SaveRoot>,
+    /// Overrides `Settings.retention` for this game when set.
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+    /// Quick-swap practice slots, kept apart from the numbered snapshots in `saves`.
+    #[serde(default)]
+    pub slots: Vec<Slot>,
 }
 impl Game {
     pub fn print_info(&self) {
@@ -60,14 +99,14 @@ impl Game {
         self.save_path = path.or(Some(home_dir));
     }
 
-    // https://docs.rs/fs_extra/latest/fs_extra/dir/fn.copy.html
     // TEST: Write exhaustive tests
     #[allow(dead_code)]
-    fn backup_all_saves(self) {
+    fn backup_all_saves(self, settings: &Settings) {
         match self.saves {
             Some(saves_list) => {
                 for mut save in saves_list {
-                    save.backup();
+                    // NOTE: backup already reports its own failures
+                    let _ = save.backup(settings);
                 }
             }
             None => {
@@ -76,11 +115,11 @@ impl Game {
         }
     }
     #[allow(dead_code)]
-    fn restore_all_saves(self) {
+    fn restore_all_saves(self, settings: &Settings) {
         match self.saves {
             Some(saves_list) => {
                 for mut save in saves_list {
-                    save.restore();
+                    let _ = save.restore(settings);
                 }
             }
             None => {
@@ -93,12 +132,25 @@ impl Game {
     Adds a season to the Show.
     # Example
     ```
-    use std::path::Path;
-    use chrono::Local;
     use oxi::config::game::Game;
+    use oxi::settings::{Settings, StorageMode};
     use std::path::PathBuf;
 
-    let settings_path: PathBuf = PathBuf::from("Documents/saves");
+    let settings = Settings {
+        save_base_path: PathBuf::from("Documents/saves"),
+        save_base_absolute: false,
+        game_conf_path: PathBuf::from(".config/oxi/"),
+        color_scheme: "dark".to_string(),
+        delete_on_restore: true,
+        storage: StorageMode::Directory,
+        encryption: None,
+        drill_interval_days: None,
+        retention: None,
+        timezone: None,
+        path_template: None,
+        destinations: Vec::new(),
+        min_copies: None,
+    };
     let prod_path: PathBuf = PathBuf::from("/mnt/games");
     let mut er = Game {
         game_title: "Elden Ring".to_string(),
@@ -109,41 +161,79 @@ impl Game {
         developer: Some("FROM Software".to_string()),
         saves: vec![].into(),
         thumbnail: vec![].into(),
+        storage: None,
+        restores: vec![],
+        delete_on_restore: None,
+        last_drill: None,
+        rules: None,
+        roots: vec![],
+        retention: None,
+        slots: vec![],
     };
-    er.add_save(prod_path, &settings_path);
+    er.add_save(prod_path, &settings);
     ```
     # This adds the save to the game, to later make the backup.
     */
-    pub fn add_save(&mut self, production_path: PathBuf, settings_path: &Path) {
-        // NOTE: Is this the most efficient manner to get the count?
+    pub fn add_save(&mut self, production_path: PathBuf, settings: &Settings) {
+        // NOTE: IDs made within the same millisecond are random relative to each other, so keep them increasing
+        let newest = self.saves.iter().flatten().map(|save| save.id).max();
+        let id = match (Ulid::new(), newest) {
+            (id, Some(newest)) if id <= newest => newest.increment().unwrap_or(id),
+            (id, _) => id,
+        };
+        // NOTE: Only for display, so running past u16::MAX is harmless
         let count = self
             .saves
             .as_ref() // NOTE: Avoids consuming
             .into_iter()
             .flatten()
-            .max_by_key(|save| save.count)
-            .map(|save| save.count + 1)
+            .map(|save| save.count.saturating_add(1))
+            .max()
             .unwrap_or(0);
         // NOTE: parent_game: helps backup_path
         let parent_game = self.game_title.clone();
-        // NOTE:  backup_path: simply a path made up of the path defined in your settings, the name of the game, and the count of the settings.
-        let backup_path: PathBuf = PathBuf::from(format!(
-            "{}/{}/{}",
-            settings_path.to_str().unwrap_or("/home/user/"),
-            &parent_game,
-            &count
-        ));
-        // NOTE: Should this be in epoch and converted later with a TZ defined by the user, or should it be converted now?
-        let saved_at = Local::now()
-            .naive_local()
-            .format("%Y-%m-%dT%H:%M:%SZ")
-            .to_string();
+        // NOTE: Stored as an instant, `Settings::format_time` converts it to the user's timezone for display
+        let saved_at = Utc::now();
+        let backup_path = self.snapshot_path(settings, id, count, saved_at);
+        // NOTE: Only the dedup store knows how to encrypt, so it wins over any other storage mode
+        let storage = match settings.encryption {
+            Some(_) => StorageMode::Dedup,
+            None => self.storage.unwrap_or(settings.storage),
+        };
+        // NOTE: Directory and Linked snapshots share a layout, so either can be linked against
+        let link_dest = match storage {
+            StorageMode::Linked => self
+                .saves
+                .as_ref()
+                .into_iter()
+                .flatten()
+                .filter(|save| matches!(save.storage, StorageMode::Directory | StorageMode::Linked))
+                .max_by_key(|save| save.id)
+                .map(|save| save.data_path()),
+            _ => None,
+        };
+        let rules = self
+            .rules
+            .clone()
+            .or_else(|| Rules::defaults_for(&production_path));
         let new_save: Save = Save {
+            id,
             count,
             backup_path,
             production_path,
             parent_game,
+            game_id: self.game_id,
             saved_at,
+            storage,
+            link_dest,
+            pre_restore_of: None,
+            rules,
+            roots: self.roots.clone(),
+            pinned: false,
+            label: None,
+            notes: None,
+            tags: Vec::new(),
+            mirrors: BTreeMap::new(),
         };
         if let Some(saves) = &mut self.saves {
             saves.push(new_save);
@@ -151,4 +241,604 @@ impl Game {
             eprintln!("There are so saves for {}", self.game_title);
         }
     }
+
+    /**
+    # Usecase
+    Brings a game from a catalog written before snapshot IDs up to date. Every save without an ID gets one made
+    from its `saved_at`, keeping their order, and its directory is renamed from `<title>/<count>` to `<title>/<id>`.
+    Each save is updated right after its directory moves, so even when an error comes back the game still matches
+    the disk and should be written back. Returns whether anything changed.
+    */
+    pub fn migrate_ids(&mut self) -> Result<bool, io::Error> {
+        let Some(saves) = self.saves.as_mut() else {
+            return Ok(false);
+        };
+        let mut pending: Vec<&mut Save> =
+            saves.iter_mut().filter(|save| save.id.is_nil()).collect();
+        if pending.is_empty() {
+            return Ok(false);
+        }
+        pending.sort_by_key(|save| save.count);
+        let mut moved: Vec<(PathBuf, PathBuf)> = Vec::new();
+        let mut previous: Option<Ulid> = None;
+        let mut result = Ok(true);
+        for save in pending {
+            let mut id = Ulid::from_datetime(SystemTime::from(save.saved_at));
+            if let Some(previous) = previous.filter(|previous| id <= *previous) {
+                id = previous.increment().unwrap_or(id);
+            }
+            previous = Some(id);
+            let target = save.backup_path.with_file_name(id.to_string());
+            if save.backup_path.exists() {
+                if let Err(err) = fs::rename(&save.backup_path, &target) {
+                    eprintln!("Could not move {:?} due to {}", save.backup_path, err);
+                    result = Err(err);
+                    break;
+                }
+            }
+            moved.push((
+                std::mem::replace(&mut save.backup_path, target),
+                save.backup_path.clone(),
+            ));
+            save.id = id;
+        }
+
+        for save in saves.iter_mut() {
+            if let Some(link_dest) = &mut save.link_dest {
+                if let Some((old, new)) = moved.iter().find(|(old, _)| link_dest.starts_with(old)) {
+                    *link_dest = new.join(link_dest.strip_prefix(old).unwrap_or(Path::new("")));
+                }
+            }
+        }
+        result
+    }
+
+    /**
+    # Usecase
+    Deletes the save with the given `id` from disk, from the mirror destinations and from this game's list of saves.
+    Blobs shared with other snapshots in the dedup store are kept until their last reference is gone.
+    */
+    pub fn delete_save(&mut self, id: Ulid, settings: &Settings) -> Result<(), io::Error> {
+        let saves = self.saves.as_mut().ok_or(io::ErrorKind::NotFound)?;
+        let index = saves
+            .iter()
+            .position(|save| save.id == id)
+            .ok_or(io::ErrorKind::NotFound)?;
+        saves[index].delete(settings)?;
+        mirror::forget(&[saves.remove(index)], settings);
+        Ok(())
+    }
+
+    /**
+    # Usecase
+    Restores the save with the given `id`, but first captures the live save as an automatic
+    "pre-restore" snapshot. The returned `RestoreEvent` links the two, so `undo_restore` can put things back.
+    If the safety snapshot cannot be taken, nothing is restored. A restore that fails after part of the live save
+    was already swapped is still recorded, so it can be undone as well.
+    */
+    pub fn restore_save(
+        &mut self,
+        id: Ulid,
+        settings: &Settings,
+    ) -> Result<RestoreEvent, io::Error> {
+        let mirror = self.mirrors_on_restore(settings);
+        let safety = self.take_safety_snapshot(id, settings)?;
+        let mut swapped = false;
+        let result = self
+            .find_save(id)?
+            .restore_tracked(settings, mirror, &mut swapped);
+        match result {
+            Ok(()) => Ok(self.record_restore(id, safety, None)),
+            Err(err) => {
+                if swapped {
+                    self.record_restore(id, safety, None);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /**
+    # Usecase
+    Like `restore_save`, but only writes back the files of snapshot `id` that `selection` picks,
+    e.g. a single `ER0000.sl2`. Everything else in the live save is left untouched.
+    The pre-restore safety snapshot still covers the whole save, so `undo_restore` works the same way.
+    */
+    pub fn restore_selected(
+        &mut self,
+        id: Ulid,
+        selection: &Selection,
+        settings: &Settings,
+    ) -> Result<RestoreEvent, io::Error> {
+        // NOTE: Fail on a bad selection before taking a snapshot for nothing
+        selection.compile()?;
+        let mirror = self.mirrors_on_restore(settings);
+        let safety = self.take_safety_snapshot(id, settings)?;
+        let files = self
+            .find_save(id)?
+            .restore_selected(settings, selection, mirror)?;
+        Ok(self.record_restore(id, safety, Some(files)))
+    }
+
+    /// Backs up the live locations snapshot `id` restores to and returns the ID of the safety snapshot.
+    fn take_safety_snapshot(&mut self, id: Ulid, settings: &Settings) -> Result<Ulid, io::Error> {
+        let (production_path, roots) = self.find_save(id).map(|save| {
+            let roots: Vec<SaveRoot> = save
+                .roots
+                .iter()
+                .filter(|root| root.restore)
+                .cloned()
+                .collect();
+            (save.production_path.clone(), roots)
+        })?;
+        fs::create_dir_all(&production_path)?;
+        for root in &roots {
+            fs::create_dir_all(&root.path)?;
+        }
+        self.add_save(production_path, settings);
+        let saves = self.saves.as_mut().ok_or(io::ErrorKind::NotFound)?;
+        let safety = saves.last_mut().ok_or(io::ErrorKind::NotFound)?;
+        safety.pre_restore_of = Some(id);
+        // NOTE: Capture exactly the roots the restore is about to write to, so it can be undone
+        safety.roots = roots;
+        let safety_id = safety.id;
+        if let Err(err) = safety.backup(settings) {
+            let _ = self.delete_save(safety_id, settings);
+            return Err(err);
+        }
+        Ok(safety_id)
+    }
+
+    fn record_restore(
+        &mut self,
+        restored: Ulid,
+        safety: Ulid,
+        files: Option<Vec<PathBuf>>,
+    ) -> RestoreEvent {
+        let event = RestoreEvent {
+            restored,
+            safety,
+            restored_at: Utc::now(),
+            files,
+        };
+        self.restores.push(event.clone());
+        event
+    }
+
+    /**
+    # Usecase
+    Undoes the most recent `restore_save` by mirroring the pre-restore snapshot back: files the
+    restore added are removed and everything else is put back as it was before the restore.
+    */
+    pub fn undo_restore(&mut self, settings: &Settings) -> Result<RestoreEvent, io::Error> {
+        let event = self
+            .restores
+            .last()
+            .cloned()
+            .ok_or(io::ErrorKind::NotFound)?;
+        let safety = self.save_mut(event.safety).ok_or(io::ErrorKind::NotFound)?;
+        safety.restore_with(settings, true)?;
+        self.restores.pop();
+        Ok(event)
+    }
+
+    /// Whether restores for this game remove files that are not in the snapshot.
+    pub fn mirrors_on_restore(&self, settings: &Settings) -> bool {
+        self.delete_on_restore.unwrap_or(settings.delete_on_restore)
+    }
+
+    /**
+    # Usecase
+    Dry run of `restore_save`: lists the live files that restoring `id` would delete.
+    Empty when this game does not mirror on restore.
+    */
+    pub fn restore_dry_run(
+        &self,
+        id: Ulid,
+        settings: &Settings,
+    ) -> Result<Vec<PathBuf>, io::Error> {
+        let save = self.find_save(id)?;
+        match self.mirrors_on_restore(settings) {
+            true => save.files_to_delete(settings),
+            false => Ok(Vec::new()),
+        }
+    }
+
+    /// This game's snapshots that match `query`, oldest first.
+    pub fn find_saves(&self, query: &SaveQuery) -> Vec<&Save> {
+        self.saves
+            .iter()
+            .flatten()
+            .filter(|save| query.matches(save))
+            .collect()
+    }
+
+    pub(crate) fn save_mut(&mut self, id: Ulid) -> Option<&mut Save> {
+        self.saves.iter_mut().flatten().find(|save| save.id == id)
+    }
+
+    /**
+    # Usecase
+    Edits the label, notes, tags or pin of snapshot `id` through `edit`, then rewrites its sidecar so a catalog
+    rebuilt from the sidecars keeps the change. A snapshot that was not backed up yet has no sidecar to rewrite.
+    */
+    pub fn edit_save(
+        &mut self,
+        id: Ulid,
+        settings: &Settings,
+        edit: impl FnOnce(&mut Save),
+    ) -> Result<(), io::Error> {
+        let save = self.save_mut(id).ok_or(io::ErrorKind::NotFound)?;
+        edit(save);
+        match save.backup_path.is_dir() {
+            true => sidecar::write(save, settings),
+            false => Ok(()),
+        }
+    }
+
+    pub fn find_save(&self, id: Ulid) -> Result<&Save, io::Error> {
+        self.saves
+            .iter()
+            .flatten()
+            .find(|save| save.id == id)
+            .ok_or(io::ErrorKind::NotFound.into())
+    }
+
+    /**
+    # Usecase
+    Compares snapshot `old` with snapshot `new` of this game using their manifests.
+    With `content`, both snapshots are extracted to a scratch directory to summarize how each modified file changed.
+    */
+    pub fn diff_saves(
+        &self,
+        old: Ulid,
+        new: Ulid,
+        settings: &Settings,
+        content: bool,
+    ) -> Result<SnapshotDiff, io::Error> {
+        let (old, new) = (self.find_save(old)?, self.find_save(new)?);
+        let mut diff = SnapshotDiff::between(
+            &old.snapshot_manifest(settings)?,
+            &new.snapshot_manifest(settings)?,
+        );
+        if content && !diff.is_empty() {
+            let scratch = tempfile::tempdir()?;
+            let (old_dir, new_dir) = (scratch.path().join("old"), scratch.path().join("new"));
+            old.extract_to(&old_dir, settings)?;
+            new.extract_to(&new_dir, settings)?;
+            diff.summarize_content(|path| old_dir.join(path), |path| new_dir.join(path))?;
+        }
+        Ok(diff)
+    }
+
+    /**
+    # Usecase
+    Shows what restoring snapshot `id` would change: the live save is the old side and the snapshot the new one,
+    so "added" files are the ones the restore brings back. Only files the snapshot's rules select are compared.
+    */
+    pub fn diff_live(
+        &self,
+        id: Ulid,
+        settings: &Settings,
+        content: bool,
+    ) -> Result<SnapshotDiff, io::Error> {
+        let save = self.find_save(id)?;
+        let mut diff = SnapshotDiff::between(
+            &save.live_manifest(settings)?,
+            &save.snapshot_manifest(settings)?,
+        );
+        if content && !diff.is_empty() {
+            let scratch = tempfile::tempdir()?;
+            save.extract_to(scratch.path(), settings)?;
+            diff.summarize_content(
+                |path| save.live_path(path),
+                |path| scratch.path().join(path),
+            )?;
+        }
+        Ok(diff)
+    }
+
+    pub fn retention_policy<'a>(&'a self, settings: &'a Settings) -> Option<&'a RetentionPolicy> {
+        self.retention.as_ref().or(settings.retention.as_ref())
+    }
+
+    /**
+    # Usecase
+    Works out which snapshots this game's retention policy would prune, without touching anything.
+    Besides pinned snapshots, the safety snapshot of the last restore is kept so it can still be undone.
+    */
+    pub fn plan_prune(&self, settings: &Settings) -> Result<PruneReport, io::Error> {
+        let mut report = PruneReport {
+            game_title: self.game_title.clone(),
+            ..PruneReport::default()
+        };
+        let Some(policy) = self.retention_policy(settings) else {
+            report.kept = self.saves.iter().flatten().map(|save| save.id).collect();
+            return Ok(report);
+        };
+        let undo_target = self.restores.last().map(|event| event.safety);
+        // NOTE: Sized newest first, so a file hardlinked between snapshots counts towards the newest one holding it.
+        // Pruning goes oldest first, and an older snapshot then only counts what deleting it actually frees
+        // Sizing dedup snapshots means reading their manifests, so only do it when capped
+        let mut bytes = HashMap::new();
+        if policy.max_bytes.is_some() {
+            let mut seen = HashSet::new();
+            for save in self.saves.iter().flatten().rev() {
+                bytes.insert(save.id, save.stored_bytes_unseen(settings, &mut seen)?);
+            }
+        }
+        let candidates: Vec<Candidate> = self
+            .saves
+            .iter()
+            .flatten()
+            .map(|save| Candidate {
+                id: save.id,
+                saved_at: save.saved_at,
+                local: settings.local_time(save.saved_at),
+                pinned: save.pinned || Some(save.id) == undo_target,
+                bytes: bytes.get(&save.id).copied().unwrap_or_default(),
+            })
+            .collect();
+        let kept = select_kept(&candidates, policy);
+        for candidate in candidates {
+            if kept.contains(&candidate.id) {
+                report.kept.push(candidate.id);
+            } else {
+                report.pruned.push(candidate.id);
+                report.freed_bytes += candidate.bytes;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Removes the saves with the given IDs from this game's list and hands them back, leaving their data on disk.
+    pub fn take_saves(&mut self, ids: &[Ulid]) -> Vec<Save> {
+        let Some(saves) = self.saves.as_mut() else {
+            return Vec::new();
+        };
+        let (taken, kept) = std::mem::take(saves)
+            .into_iter()
+            .partition(|save| ids.contains(&save.id));
+        *saves = kept;
+        taken
+    }
+
+    /**
+    # Usecase
+    Where a new snapshot goes: `Settings.path_template` rendered for this game, falling back to the default
+    layout if the template is invalid. Gets the snapshot ID appended if that directory is already taken.
+    */
+    fn snapshot_path(
+        &self,
+        settings: &Settings,
+        snapshot: Ulid,
+        count: u16,
+        saved_at: DateTime<Utc>,
+    ) -> PathBuf {
+        let values = TemplateValues {
+            store: &settings.save_base_path,
+            title: &self.game_title,
+            game_id: self.game_id,
+            snapshot,
+            count,
+            saved_at,
+        };
+        let template = settings
+            .path_template
+            .as_deref()
+            .unwrap_or(template::DEFAULT_TEMPLATE);
+        let path = template::render(template, &values).unwrap_or_else(|err| {
+            eprintln!("Falling back to the default snapshot layout: {}", err);
+            settings
+                .save_base_path
+                .join(template::sanitize_segment(&self.game_title))
+                .join(snapshot.to_string())
+        });
+        // NOTE: `{timestamp}` and `{count}` can repeat, e.g. two snapshots in one second or counts from before a prune
+        let taken = self
+            .saves
+            .iter()
+            .flatten()
+            .any(|save| save.backup_path == path);
+        match taken || path.exists() {
+            true => {
+                let mut name = path.file_name().unwrap_or_default().to_os_string();
+                name.push(format!("-{}", snapshot));
+                path.with_file_name(name)
+            }
+            false => path,
+        }
+    }
+
+    /**
+    # Usecase
+    Writes sidecars for snapshots taken before they existed, so those can be recovered by `rebuild_catalog` too.
+    Existing sidecars are left alone. Returns how many were written.
+    */
+    pub fn backfill_sidecars(&mut self, settings: &Settings) -> Result<usize, io::Error> {
+        let mut written = 0;
+        for save in self.saves.iter_mut().flatten() {
+            save.game_id = self.game_id;
+            if save.backup_path.is_dir() && !save.backup_path.join(SIDECAR_NAME).exists() {
+                sidecar::write(save, settings)?;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    /**
+    # Usecase
+    Encrypts the dedup snapshots taken before `settings.encryption` was turned on, see
+    `Store::encrypt_legacy_manifest`, and seals their sidecars. Until then the store refuses to read them.
+    Returns how many snapshots were migrated.
+    */
+    pub fn encrypt_legacy(&self, settings: &Settings) -> Result<usize, io::Error> {
+        let store = Store::open_with(settings)?;
+        let mut migrated = 0;
+        for save in self.saves.iter().flatten() {
+            if save.storage != StorageMode::Dedup || !save.manifest_path().exists() {
+                continue;
+            }
+            let mut sealed = store.encrypt_legacy_manifest(&save.manifest_path())?;
+            for (_, part) in save.root_saves()? {
+                sealed |= store.encrypt_legacy_manifest(&part.manifest_path())?;
+            }
+            if sealed {
+                sidecar::write(save, settings)?;
+                migrated += 1;
+            }
+        }
+        Ok(migrated)
+    }
+
+    /**
+    # Usecase
+    Renames the game. Existing snapshots and slots stay where they are, their paths are recorded in the catalog,
+    so nothing is orphaned; only snapshots taken from now on use the new title in their path.
+    Their sidecars are rewritten like in `edit_save`, so a catalog rebuilt from them keeps the new title.
+    Every sidecar is attempted; the first error is handed back.
+    */
+    pub fn rename(&mut self, title: &str, settings: &Settings) -> Result<(), io::Error> {
+        self.game_title = title.to_string();
+        let mut result = Ok(());
+        for save in self.saves.iter_mut().flatten() {
+            save.parent_game = title.to_string();
+            if save.backup_path.is_dir() {
+                if let Err(err) = sidecar::write(save, settings) {
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /**
+    # Usecase
+    Saves the live save at `production_path` into the slot called `name`, creating the slot if needed and
+    overwriting it otherwise. Nothing is added to `saves`.
+    Refused while `settings.encryption` is set, since a slot is a plain copy that would leave the save unencrypted.
+    */
+    pub fn save_slot(
+        &mut self,
+        name: &str,
+        production_path: PathBuf,
+        settings: &Settings,
+    ) -> Result<(), io::Error> {
+        if settings.encryption.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "slots are plain copies and cannot be used while encryption is on",
+            ));
+        }
+        let start = std::time::Instant::now();
+        let saved_at = Utc::now();
+        let rules = self
+            .rules
+            .clone()
+            .or_else(|| Rules::defaults_for(&production_path));
+        let slot = Slot {
+            name: name.to_string(),
+            path: settings
+                .save_base_path
+                .join(template::sanitize_segment(&self.game_title))
+                .join(SLOTS_DIR)
+                .join(name),
+            production_path,
+            saved_at,
+            rules,
+        };
+        let files = slot.save()?;
+        match self.slots.iter_mut().find(|slot| slot.name == name) {
+            Some(existing) => *existing = slot,
+            None => self.slots.push(slot),
+        }
+        println!(
+            "\x1b[32mSaved slot \x1b[34m{}\x1b[0m ({} files) in \x1b[36m{:.2?}\x1b[0m",
+            name,
+            files,
+            start.elapsed()
+        );
+        Ok(())
+    }
+
+    /// Makes the live save match the slot called `name`.
+    pub fn load_slot(&self, name: &str) -> Result<(), io::Error> {
+        let start = std::time::Instant::now();
+        let slot = self
+            .slots
+            .iter()
+            .find(|slot| slot.name == name)
+            .ok_or(io::ErrorKind::NotFound)?;
+        let files = slot.load()?;
+        println!(
+            "\x1b[32mLoaded slot \x1b[34m{}\x1b[0m ({} files) in \x1b[36m{:.2?}\x1b[0m",
+            name,
+            files,
+            start.elapsed()
+        );
+        Ok(())
+    }
+
+    pub fn delete_slot(&mut self, name: &str) -> Result<(), io::Error> {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| slot.name == name)
+            .ok_or(io::ErrorKind::NotFound)?;
+        match fs::remove_dir_all(&self.slots[index].path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        self.slots.remove(index);
+        Ok(())
+    }
+
+    /// Verifies every snapshot of this game against its manifest.
+    pub fn verify(&self, settings: &Settings) -> Vec<SaveVerification> {
+        self.saves
+            .iter()
+            .flatten()
+            .map(|save| SaveVerification {
+                game_title: self.game_title.clone(),
+                id: save.id,
+                count: save.count,
+                result: save.verify(settings),
+            })
+            .collect()
+    }
+
+    /// Whether this game has snapshots and has not had a restore drill within `interval`.
+    pub fn drill_due(&self, interval: Duration, now: DateTime<Utc>) -> bool {
+        if self.saves.iter().flatten().next().is_none() {
+            return false;
+        }
+        match &self.last_drill {
+            Some(record) => now - record.ran_at >= interval,
+            None => true,
+        }
+    }
+
+    /// Runs a restore drill on the newest snapshot and records the outcome in `last_drill`.
+    pub fn drill_latest(
+        &mut self,
+        settings: &Settings,
+        now: DateTime<Utc>,
+    ) -> Option<SaveVerification> {
+        let save = self.saves.iter().flatten().max_by_key(|save| save.id)?;
+        let verification = SaveVerification {
+            game_title: self.game_title.clone(),
+            id: save.id,
+            count: save.count,
+            result: save.drill(settings),
+        };
+        self.last_drill = Some(DrillRecord {
+            id: save.id,
+            ran_at: now,
+            passed: verification.is_ok(),
+        });
+        Some(verification)
+    }
 }
@@ -0,0 +1,62 @@
+use crate::config::copy::copy_file;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// How many files were hardlinked to the previous snapshot and how many had to be copied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LinkReport {
+    pub linked: usize,
+    pub copied: usize,
+}
+
+/// Same quick check rsync uses: a file is unchanged if its size and mtime match.
+fn unchanged(current: &fs::Metadata, previous: &Path) -> bool {
+    match fs::metadata(previous) {
+        Ok(old) => {
+            old.is_file()
+                && old.len() == current.len()
+                && old.modified().ok() == current.modified().ok()
+        }
+        Err(_) => false,
+    }
+}
+
+/**
+# Usecase
+Copies `files` from `src` into `dst` the way `rsync --link-dest` does: files that are unchanged since the
+snapshot in `previous` are hardlinked to it instead of copied, so `dst` is still a complete,
+browsable folder but only costs the space of the files that changed.
+
+Copied files keep their mtime (see `copy_file`) so the next snapshot can link against them.
+*/
+pub fn link_snapshot(
+    src: &Path,
+    files: &[PathBuf],
+    dst: &Path,
+    previous: Option<&Path>,
+) -> io::Result<LinkReport> {
+    let mut report = LinkReport::default();
+    for relative in files {
+        let source = src.join(relative);
+        let target = dst.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if target.exists() {
+            fs::remove_file(&target)?;
+        }
+        let metadata = fs::metadata(&source)?;
+        if let Some(old) = previous.map(|previous| previous.join(relative)) {
+            // NOTE: Linking fails across filesystems, in which case we just copy
+            if unchanged(&metadata, &old) && fs::hard_link(&old, &target).is_ok() {
+                report.linked += 1;
+                continue;
+            }
+        }
+        copy_file(&source, &target)?;
+        report.copied += 1;
+    }
+    Ok(report)
+}
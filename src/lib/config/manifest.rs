@@ -0,0 +1,101 @@
+use crate::config::{walk_files, write_atomic};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A single file recorded in a snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Hex encoded blake3 hash of the file's contents.
+    pub hash: String,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch, when known.
+    #[serde(default)]
+    pub modified: Option<i64>,
+}
+
+pub fn modified_secs(metadata: &fs::Metadata) -> Option<i64> {
+    let modified = metadata.modified().ok()?;
+    match modified.duration_since(UNIX_EPOCH) {
+        Ok(after) => Some(after.as_secs() as i64),
+        Err(before) => Some(-(before.duration().as_secs() as i64)),
+    }
+}
+
+/// The inverse of `modified_secs`.
+pub fn from_secs(secs: i64) -> SystemTime {
+    match secs >= 0 {
+        true => UNIX_EPOCH + Duration::from_secs(secs as u64),
+        false => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()),
+    }
+}
+
+/// Hashes a file with plain blake3 and records its size and mtime.
+pub fn hash_file(path: &Path) -> io::Result<ManifestEntry> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let mut hasher = blake3::Hasher::new();
+    let size = io::copy(&mut BufReader::new(file), &mut hasher)?;
+    Ok(ManifestEntry {
+        hash: hasher.finalize().to_hex().to_string(),
+        size,
+        modified: modified_secs(&metadata),
+    })
+}
+
+/**
+# Usecase
+Describes the contents of a snapshot as a map of relative path to content hash.
+Paths are relative to the production directory that was backed up.
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Manifest {
+    pub files: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Builds a manifest by hashing every file below `root`.
+    pub fn from_dir(root: &Path) -> io::Result<Manifest> {
+        Manifest::from_files(root, &walk_files(root)?)
+    }
+
+    /// Builds a manifest by hashing the given `files` (relative to `root`).
+    pub fn from_files(root: &Path, files: &[PathBuf]) -> io::Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for relative in files {
+            let entry = hash_file(&root.join(relative))?;
+            manifest.files.insert(relative.clone(), entry);
+        }
+        Ok(manifest)
+    }
+
+    /// Adds the entries of `other` with their paths placed under `prefix`.
+    pub fn absorb(&mut self, other: Manifest, prefix: &Path) {
+        self.files.extend(
+            other
+                .files
+                .into_iter()
+                .map(|(path, entry)| (prefix.join(path), entry)),
+        );
+    }
+
+    pub fn load(path: &Path) -> io::Result<Manifest> {
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader).map_err(io::Error::other)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        write_atomic(path, &json)
+    }
+
+    /// Sum of the sizes of every file in the snapshot.
+    pub fn total_size(&self) -> u64 {
+        self.files.values().map(|entry| entry.size).sum()
+    }
+}
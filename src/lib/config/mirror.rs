@@ -0,0 +1,304 @@
+use crate::config::{
+    backend::{
+        forget_snapshots, open_backend, push_snapshot, remote_manifests, ObjectKind, StorageBackend,
+    },
+    game::Game,
+    manifest::Manifest,
+    save::Save,
+    store::Store,
+    timestamps,
+};
+use crate::settings::{BackendConfig, Destination, Settings};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io};
+use ulid::Ulid;
+
+/**
+# Usecase
+Where a snapshot stands with one destination, kept on the `Save` by destination name. A destination with no entry
+has never been tried, e.g. because it was added after the snapshot was taken.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum MirrorState {
+    Synced {
+        #[serde(with = "timestamps")]
+        at: DateTime<Utc>,
+    },
+    Failed {
+        #[serde(with = "timestamps")]
+        at: DateTime<Utc>,
+        error: String,
+    },
+}
+
+/// Whether `save` is known to be complete at the destination called `name`.
+pub fn is_synced(save: &Save, name: &str) -> bool {
+    matches!(save.mirrors.get(name), Some(MirrorState::Synced { .. }))
+}
+
+/**
+# Usecase
+Opens `destination` if it can be reached right now. A local destination has to exist already, so a drive that is
+not plugged in is reported as unavailable instead of being filled in under its empty mount point.
+*/
+pub fn open_destination(destination: &Destination) -> Result<Box<dyn StorageBackend>, io::Error> {
+    if let BackendConfig::Local { path } = &destination.backend {
+        if !path.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{:?} is not mounted", path),
+            ));
+        }
+    }
+    let backend = open_backend(&destination.backend)?;
+    // NOTE: Any answer, found or not, means the destination is there and accepts our credentials
+    backend.contains(ObjectKind::Manifest, "probe")?;
+    Ok(backend)
+}
+
+/// Pushes `save` to one destination and records how it went.
+fn sync_to(save: &mut Save, settings: &Settings, name: &str, backend: &dyn StorageBackend) -> bool {
+    let state = match push_snapshot(save, settings, backend) {
+        Ok(_) => MirrorState::Synced { at: Utc::now() },
+        Err(err) => MirrorState::Failed {
+            at: Utc::now(),
+            error: err.to_string(),
+        },
+    };
+    let synced = matches!(state, MirrorState::Synced { .. });
+    save.mirrors.insert(name.to_string(), state);
+    synced
+}
+
+/**
+# Usecase
+Mirrors a freshly written `save` to every destination in `settings` it is not synced to yet.
+A destination that cannot be reached is recorded as failed, for `catch_up` to retry later; the snapshot in
+`save_base_path` is complete either way, so this never fails the backup. Returns the number of places the
+snapshot is now kept in, `save_base_path` included.
+*/
+pub fn mirror_save(save: &mut Save, settings: &Settings) -> usize {
+    for destination in &settings.destinations {
+        if is_synced(save, &destination.name) {
+            continue;
+        }
+        let synced = match open_destination(destination) {
+            Ok(backend) => sync_to(save, settings, &destination.name, backend.as_ref()),
+            Err(err) => {
+                save.mirrors.insert(
+                    destination.name.clone(),
+                    MirrorState::Failed {
+                        at: Utc::now(),
+                        error: err.to_string(),
+                    },
+                );
+                false
+            }
+        };
+        match synced {
+            true => println!(
+                "\x1b[32mMirrored \x1b[34m{}\x1b[32m to \x1b[35m{}\x1b[0m",
+                save.parent_game, destination.name
+            ),
+            false => eprintln!(
+                "\x1b[33mCould not mirror {} to {}, it will be retried later\x1b[0m",
+                save.parent_game, destination.name
+            ),
+        }
+    }
+    copies(save, settings)
+}
+
+/// What a `catch_up` run did. Destinations that could not be reached are left alone and listed by name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CatchUpReport {
+    pub synced: usize,
+    pub failed: usize,
+    pub unavailable: Vec<String>,
+}
+
+/**
+# Usecase
+Pushes every snapshot a destination is missing, e.g. once an external drive is plugged back in. Runs on every
+start, destinations that still cannot be reached are only listed.
+Snapshots no longer in `save_base_path` are skipped, there is nothing left to copy. The caller writes the catalog
+afterwards to keep the new sync state.
+*/
+pub fn catch_up(games: &mut [Game], settings: &Settings) -> CatchUpReport {
+    let mut report = CatchUpReport::default();
+    for destination in &settings.destinations {
+        let pending = || {
+            games
+                .iter()
+                .flat_map(|game| game.saves.iter().flatten())
+                .any(|save| !is_synced(save, &destination.name) && save.backup_path.exists())
+        };
+        if !pending() {
+            continue;
+        }
+        let backend = match open_destination(destination) {
+            Ok(backend) => backend,
+            Err(_) => {
+                report.unavailable.push(destination.name.clone());
+                continue;
+            }
+        };
+        for save in games
+            .iter_mut()
+            .flat_map(|game| game.saves.iter_mut().flatten())
+        {
+            if is_synced(save, &destination.name) || !save.backup_path.exists() {
+                continue;
+            }
+            match sync_to(save, settings, &destination.name, backend.as_ref()) {
+                true => report.synced += 1,
+                false => report.failed += 1,
+            }
+        }
+    }
+    report
+}
+
+/**
+# Usecase
+Removes `saves` from every destination they are synced to, once the user deleted or pruned them, so mirrors do not
+keep snapshots the catalog has let go of. Each destination collects its unused blobs once for all of them. Never for snapshots dropped because the local copy is damaged or gone, the
+mirrors may hold the only good copy. A destination that cannot be reached keeps its copy; this is only
+reported, since an unplugged drive must not stop a prune. Returns the number of destinations they were removed from.
+*/
+pub fn forget(saves: &[Save], settings: &Settings) -> usize {
+    let mut forgotten = 0;
+    for destination in &settings.destinations {
+        let ids: Vec<Ulid> = saves
+            .iter()
+            .filter(|save| is_synced(save, &destination.name))
+            .map(|save| save.id)
+            .collect();
+        if ids.is_empty() {
+            continue;
+        }
+        match open_destination(destination)
+            .and_then(|backend| forget_snapshots(backend.as_ref(), &ids))
+        {
+            Ok(_) => forgotten += 1,
+            Err(err) => eprintln!(
+                "\x1b[33mCould not remove {} snapshots from {} due to {}\x1b[0m",
+                ids.len(),
+                destination.name,
+                err
+            ),
+        }
+    }
+    forgotten
+}
+
+/// The store manifests of `save` from the first destination it is synced to that can be read, once the local copy is gone.
+pub fn find_manifests(save: &Save, settings: &Settings, store: &Store) -> Option<Vec<Manifest>> {
+    settings
+        .destinations
+        .iter()
+        .filter(|destination| is_synced(save, &destination.name))
+        .find_map(|destination| {
+            open_destination(destination)
+                .and_then(|backend| remote_manifests(backend.as_ref(), save.id, store))
+                .map_err(|err| {
+                    eprintln!(
+                        "\x1b[33mCould not read {} {} from {} due to {}\x1b[0m",
+                        save.parent_game, save.id, destination.name, err
+                    )
+                })
+                .ok()
+        })
+}
+
+/// Places `save` is kept in: `save_base_path` if it is still there, plus each configured destination it is synced to.
+pub fn copies(save: &Save, settings: &Settings) -> usize {
+    let primary = usize::from(save.backup_path.exists());
+    primary
+        + settings
+            .destinations
+            .iter()
+            .filter(|destination| is_synced(save, &destination.name))
+            .count()
+}
+
+/// A snapshot kept in fewer places than asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortfall {
+    pub game_title: String,
+    pub id: Ulid,
+    pub copies: usize,
+}
+
+/**
+# Usecase
+Every snapshot kept in fewer than `min_copies` places, `Settings::min_copies` or else every configured place
+when `None`. Pre-restore safety snapshots are included, they are what an undo relies on.
+*/
+pub fn under_replicated(
+    games: &[Game],
+    settings: &Settings,
+    min_copies: Option<usize>,
+) -> Vec<Shortfall> {
+    let wanted = min_copies
+        .or(settings.min_copies)
+        .unwrap_or(settings.destinations.len() + 1);
+    games
+        .iter()
+        .flat_map(|game| game.saves.iter().flatten().map(move |save| (game, save)))
+        .filter_map(|(game, save)| {
+            let copies = copies(save, settings);
+            (copies < wanted).then(|| Shortfall {
+                game_title: game.game_title.clone(),
+                id: save.id,
+                copies,
+            })
+        })
+        .collect()
+}
+
+/// How one destination is doing across the whole catalog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DestinationHealth {
+    pub name: String,
+    /// Why it cannot be reached right now, `None` if it can.
+    pub unavailable: Option<String>,
+    pub synced: usize,
+    pub pending: usize,
+    /// The most recent failure of any snapshot, if one is still pending.
+    pub last_error: Option<(DateTime<Utc>, String)>,
+}
+
+/// Reachability and sync counts for every destination in `settings`.
+pub fn health(games: &[Game], settings: &Settings) -> Vec<DestinationHealth> {
+    settings
+        .destinations
+        .iter()
+        .map(|destination| {
+            let mut states: BTreeMap<bool, usize> = BTreeMap::new();
+            let mut last_error: Option<(DateTime<Utc>, String)> = None;
+            for save in games.iter().flat_map(|game| game.saves.iter().flatten()) {
+                *states
+                    .entry(is_synced(save, &destination.name))
+                    .or_default() += 1;
+                if let Some(MirrorState::Failed { at, error }) = save.mirrors.get(&destination.name)
+                {
+                    if last_error.as_ref().is_none_or(|(last, _)| at > last) {
+                        last_error = Some((*at, error.clone()));
+                    }
+                }
+            }
+            DestinationHealth {
+                name: destination.name.clone(),
+                unavailable: open_destination(destination)
+                    .err()
+                    .map(|err| err.to_string()),
+                synced: states.get(&true).copied().unwrap_or_default(),
+                pending: states.get(&false).copied().unwrap_or_default(),
+                last_error,
+            }
+        })
+        .collect()
+}
@@ -1,6 +1,28 @@
+pub mod archive;
+pub mod backend;
+pub mod copy;
+pub mod crypto;
+pub mod diff;
+pub mod drill;
+pub mod fsck;
 pub mod game;
+pub mod linked;
+pub mod manifest;
+pub mod mirror;
+pub mod relocate;
+pub mod restore;
+pub mod retention;
+pub mod rules;
+pub mod s3;
 pub mod save;
+pub mod search;
+pub mod sidecar;
+pub mod slots;
 pub mod steam;
+pub mod store;
+pub mod template;
+pub mod timestamps;
+pub mod verify;
 use serde::{
     de::{DeserializeOwned, Error},
     Deserialize, Serialize,
@@ -8,7 +30,7 @@ use serde::{
 use std::{
     fs::{self, File},
     io::{self, Read, Write},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,6 +53,43 @@ fn test_create_dir(path: &PathBuf) -> Result<(), io::Error> {
 
     Ok(())
 }
+
+/// Checks that `name` can be used as a single directory name inside a snapshot.
+pub(crate) fn check_plain_name(name: &str) -> Result<(), io::Error> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(std::path::Component::Normal(_)), None) => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not a valid name", name),
+        )),
+    }
+}
+
+/**
+# Usecase
+Recursively lists every regular file below `root`, relative to `root` and sorted.
+Symlinks are skipped so a snapshot never follows a link out of the save directory.
+*/
+pub(crate) fn walk_files(root: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        for entry in fs::read_dir(root.join(&relative))? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = relative.join(entry.file_name());
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
 pub fn write_conf<T>(conf: Vec<T>, pth: &Path)
 // pub fn write_conf<T>(conf: Vec<T>, pth: PathBuf -> serde_json::Result<()>)
 where
@@ -44,6 +103,27 @@ where
         eprintln!("Error creating file {:?}: {}", pth, err);
     }
 }
+/// Like `write_conf`, but writes through a temporary sibling that replaces `pth` in one rename and reports failures.
+pub fn save_conf<T>(conf: &[T], pth: &Path) -> Result<(), io::Error>
+where
+    T: Serialize,
+{
+    let mut tmp = tempfile::NamedTempFile::new_in(pth.parent().unwrap_or(Path::new(".")))?;
+    serde_json::to_writer(&mut tmp, conf).map_err(io::Error::other)?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(pth).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// Writes `data` to `pth` the way `save_conf` does, so a crash leaves either the old file or the new one.
+pub fn write_atomic(pth: &Path, data: &[u8]) -> Result<(), io::Error> {
+    let mut tmp = tempfile::NamedTempFile::new_in(pth.parent().unwrap_or(Path::new(".")))?;
+    tmp.write_all(data)?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(pth).map_err(|err| err.error)?;
+    Ok(())
+}
+
 pub fn create_config() {
     let default_settings = r#"[
         {
@@ -130,6 +210,20 @@ where
     }
 }
 
+/**
+# Usecase
+Resolves a path from oxi.json against the home directory. Paths there are always relative to home, a leading `/`
+included, so "/Documents/Saves" is `~/Documents/Saves`. Works on the raw path, so names that are not valid UTF-8
+survive unchanged instead of being mangled by a round trip through a string.
+*/
+pub fn under_home(home: &Path, path: &Path) -> PathBuf {
+    let relative: PathBuf = path
+        .components()
+        .filter(|component| !matches!(component, Component::RootDir | Component::Prefix(_)))
+        .collect();
+    home.join(relative)
+}
+
 /**
 # Usecase
 Just Generates an expanded ~/
@@ -0,0 +1,204 @@
+use crate::config::{game::Game, restore::replace_file, save_conf, walk_files};
+use crate::settings::Settings;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Left in the new base while a copy is under way, holding the old base, so an interrupted move can be resumed.
+pub const RELOCATE_MARKER: &str = ".oxi-relocating";
+
+/// How far a relocation has come, reported after every file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RelocateProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// What a relocation did. `renamed` means the store was moved in one rename and nothing had to be copied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RelocateReport {
+    pub renamed: bool,
+    pub copied: usize,
+    /// Already in place from an earlier, interrupted run.
+    pub skipped: usize,
+    /// Recreated as hardlinks, as they were in the old store.
+    pub linked: usize,
+    pub bytes: u64,
+}
+
+fn marker_contents(old_base: &Path) -> Vec<u8> {
+    old_base.as_os_str().as_encoded_bytes().to_vec()
+}
+
+/// Whether `dst` is a finished copy of `src`. Copies keep the modification time, so size and mtime have to match.
+fn already_copied(src: &fs::Metadata, dst: &Path) -> bool {
+    match fs::metadata(dst) {
+        Ok(existing) => {
+            existing.len() == src.len() && existing.modified().ok() == src.modified().ok()
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino())).filter(|_| metadata.nlink() > 1)
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/**
+# Usecase
+Copies every file below `old_base` into `new_base`. Files already copied by an earlier run are skipped, each
+file is written through a synced sibling and renamed into place, so an interrupted copy never leaves a truncated
+file behind. Files hardlinked together, as `StorageMode::Linked` snapshots are, stay hardlinked in the copy.
+*/
+fn copy_store(
+    old_base: &Path,
+    new_base: &Path,
+    report: &mut RelocateReport,
+    progress: &mut dyn FnMut(&RelocateProgress),
+) -> Result<(), io::Error> {
+    let files = walk_files(old_base)?;
+    let sizes = files
+        .iter()
+        .map(|relative| fs::metadata(old_base.join(relative)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut state = RelocateProgress {
+        files_total: files.len(),
+        bytes_total: sizes.iter().map(|metadata| metadata.len()).sum(),
+        ..RelocateProgress::default()
+    };
+    let mut first_copy: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    for (relative, metadata) in files.iter().zip(&sizes) {
+        let (src, dst) = (old_base.join(relative), new_base.join(relative));
+        let linked_to = inode(metadata).and_then(|key| match first_copy.get(&key) {
+            Some(first) => Some(first.clone()),
+            None => {
+                first_copy.insert(key, dst.clone());
+                None
+            }
+        });
+        match linked_to {
+            Some(first) => {
+                match dst.parent() {
+                    Some(parent) => fs::create_dir_all(parent)?,
+                    None => return Err(io::ErrorKind::InvalidInput.into()),
+                }
+                if dst.exists() {
+                    fs::remove_file(&dst)?;
+                }
+                fs::hard_link(first, &dst)?;
+                report.linked += 1;
+            }
+            None if already_copied(metadata, &dst) => report.skipped += 1,
+            None => {
+                replace_file(&src, &dst)?;
+                report.copied += 1;
+                report.bytes += metadata.len();
+            }
+        }
+        state.files_done += 1;
+        state.bytes_done += metadata.len();
+        progress(&state);
+    }
+    Ok(())
+}
+
+/// Points `path` at `new_base` if it was below `old_base`.
+fn rebase(path: &mut PathBuf, old_base: &Path, new_base: &Path) {
+    if let Ok(rest) = path.strip_prefix(old_base) {
+        *path = new_base.join(rest);
+    }
+}
+
+/**
+# Usecase
+Moves the whole backup store, snapshots, blob store and slots alike, from `settings.save_base_path` to
+`new_base`, then points every `backup_path`, `link_dest` and slot in the catalog at the new place.
+On the same filesystem this is a single rename. Otherwise the store is copied, reporting `progress` after each
+file, and can be resumed by calling this again with the same arguments after an interruption.
+The catalog at `conf_path` is written once the copy is complete. With `keep_old` the store is always copied,
+never renamed. `settings.save_base_path` is updated to `new_base`; the caller writes the settings back and only
+then calls `finish`, so the old store is still there for as long as anything may point at it.
+*/
+pub fn relocate(
+    games: &mut [Game],
+    settings: &mut Settings,
+    conf_path: &Path,
+    new_base: &Path,
+    keep_old: bool,
+    progress: &mut dyn FnMut(&RelocateProgress),
+) -> Result<RelocateReport, io::Error> {
+    let old_base = settings.save_base_path.clone();
+    let mut report = RelocateReport::default();
+    if old_base == new_base {
+        return Ok(report);
+    }
+    if new_base.starts_with(&old_base) || old_base.starts_with(new_base) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the new backup location cannot be inside the old one or the other way around",
+        ));
+    }
+    let marker = new_base.join(RELOCATE_MARKER);
+    if old_base.exists() {
+        let resuming = fs::read(&marker).is_ok_and(|from| from == marker_contents(&old_base));
+        let occupied = new_base.exists() && fs::read_dir(new_base)?.next().is_some();
+        if occupied && !resuming {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{:?} is not empty", new_base),
+            ));
+        }
+        if let Some(parent) = new_base.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // NOTE: An empty directory left over from a failed attempt would make the rename fail
+        if !occupied && new_base.exists() {
+            fs::remove_dir(new_base)?;
+        }
+        report.renamed = !keep_old && !occupied && fs::rename(&old_base, new_base).is_ok();
+        if !report.renamed {
+            fs::create_dir_all(new_base)?;
+            fs::write(&marker, marker_contents(&old_base))?;
+            copy_store(&old_base, new_base, &mut report, progress)?;
+        }
+    }
+
+    for game in games.iter_mut() {
+        for save in game.saves.iter_mut().flatten() {
+            rebase(&mut save.backup_path, &old_base, new_base);
+            if let Some(link_dest) = &mut save.link_dest {
+                rebase(link_dest, &old_base, new_base);
+            }
+        }
+        for slot in &mut game.slots {
+            rebase(&mut slot.path, &old_base, new_base);
+        }
+    }
+    save_conf(games, conf_path)?;
+    settings.save_base_path = new_base.to_path_buf();
+    Ok(report)
+}
+
+/// Completes a `relocate` from `old_base` to `new_base` once the settings point at `new_base`, removing the old
+/// store unless `keep_old` is set.
+pub fn finish(old_base: &Path, new_base: &Path, keep_old: bool) -> Result<(), io::Error> {
+    let marker = new_base.join(RELOCATE_MARKER);
+    if marker.exists() {
+        fs::remove_file(&marker)?;
+    }
+    if !keep_old && old_base.exists() {
+        fs::remove_dir_all(old_base)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,249 @@
+use crate::config::{copy::copy_file, timestamps, walk_files};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+use ulid::Ulid;
+
+/**
+# Usecase
+Records a restore done through `Game::restore_save`, so it can be undone later.
+`safety` is the ID of the automatic snapshot of the live save taken right before `restored` was written over it.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RestoreEvent {
+    pub restored: Ulid,
+    pub safety: Ulid,
+    #[serde(with = "timestamps")]
+    pub restored_at: DateTime<Utc>,
+    /// Set for selective restores: the files that were written back or removed. `None` means the whole snapshot.
+    #[serde(default)]
+    pub files: Option<Vec<PathBuf>>,
+}
+
+/// A hidden sibling of `target`, e.g. `.Saves.oxi-staging` next to `Saves`, so renames never cross filesystems.
+pub fn sibling_path(target: &Path, suffix: &str) -> Result<PathBuf, io::Error> {
+    let name = target.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+    let mut sibling = OsString::from(".");
+    sibling.push(name);
+    sibling.push(".oxi-");
+    sibling.push(suffix);
+    Ok(target.with_file_name(sibling))
+}
+
+/// The directory a restore over `production_path` swaps. A save directory that is a symlink, as in Proton
+/// prefixes, stays one: its target is swapped instead.
+pub fn swap_target(production_path: &Path) -> Result<PathBuf, io::Error> {
+    match fs::symlink_metadata(production_path) {
+        Ok(metadata) if metadata.is_symlink() => fs::canonicalize(production_path),
+        _ => Ok(production_path.to_path_buf()),
+    }
+}
+
+/// Replaces the single file `target` with a copy of `src`, through a synced sibling that is renamed over it.
+pub fn replace_file(src: &Path, target: &Path) -> Result<(), io::Error> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial = sibling_path(target, "partial")?;
+    let result = copy_file(src, &partial)
+        .and_then(|_| File::open(&partial)?.sync_all())
+        .and_then(|_| fs::rename(&partial, target));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+#[cfg(unix)]
+fn make_link(original: &Path, link: &Path) -> Result<(), io::Error> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn make_link(original: &Path, link: &Path) -> Result<(), io::Error> {
+    match link.parent().map(|parent| parent.join(original).is_dir()) {
+        Some(true) => std::os::windows::fs::symlink_dir(original, link),
+        _ => std::os::windows::fs::symlink_file(original, link),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn make_link(_original: &Path, _link: &Path) -> Result<(), io::Error> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/**
+# Usecase
+Recreates in `staging` what snapshots never hold but the live save at `live` has: empty directories, and every
+symlink as a link to the same target, e.g. a Proton prefix pointing into another drive.
+Without this a restore, overlaid or mirrored, would drop them when `staging` is swapped in. Links are never
+followed, and paths the snapshot already wrote into `staging` are left as they are.
+*/
+pub fn carry_over_links(live: &Path, staging: &Path) -> Result<(), io::Error> {
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        for entry in fs::read_dir(live.join(&relative))? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = relative.join(entry.file_name());
+            let staged = fs::symlink_metadata(staging.join(&path));
+            match staged {
+                // NOTE: The snapshot put a file where the live save has a directory or link
+                Ok(metadata) if !metadata.is_dir() => {}
+                _ if file_type.is_dir() => {
+                    if fs::read_dir(entry.path())?.next().is_none() {
+                        fs::create_dir_all(staging.join(&path))?;
+                    }
+                    pending.push(path);
+                }
+                Err(_) if file_type.is_symlink() => {
+                    if let Some(parent) = staging.join(&path).parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    make_link(&fs::read_link(entry.path())?, &staging.join(&path))?;
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<(), io::Error> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<(), io::Error> {
+    Ok(())
+}
+
+/// Flushes every file and directory below `root` to disk.
+pub fn sync_tree(root: &Path) -> Result<(), io::Error> {
+    let files = walk_files(root)?;
+    for relative in &files {
+        File::open(root.join(relative))?.sync_all()?;
+    }
+    let dirs: BTreeSet<&Path> = files
+        .iter()
+        .flat_map(|path| path.ancestors().skip(1))
+        .collect();
+    for dir in dirs {
+        sync_dir(&root.join(dir))?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> Result<(), io::Error> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid, NUL terminated C strings for the duration of the call
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange(_a: &Path, _b: &Path) -> Result<(), io::Error> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/**
+# Usecase
+Replaces the directory `target` with the fully written `staging` directory. `target` must not be a symlink,
+or the link itself would be replaced; resolve it first.
+On Linux both are swapped in one atomic `renameat2(RENAME_EXCHANGE)`. Elsewhere the old directory is
+renamed aside first and put back if moving `staging` into place fails.
+Either way `target` ends up entirely old or entirely new, and `staging` is gone afterwards.
+*/
+pub fn swap_in(staging: &Path, target: &Path) -> Result<(), io::Error> {
+    swap_keeping_old(staging, target).map(Swapped::commit)
+}
+
+/// A directory swapped in by `swap_keeping_old`, whose old contents are kept until it is committed or rolled back.
+pub struct Swapped {
+    target: PathBuf,
+    old: Option<PathBuf>,
+}
+
+impl Swapped {
+    /// Drops the old contents. Failing to clean them up does not undo the swap.
+    pub fn commit(self) {
+        if let Some(old) = &self.old {
+            let _ = fs::remove_dir_all(old);
+        }
+    }
+
+    /// Puts the old contents back in place, or removes `target` again if there was nothing before.
+    pub fn roll_back(self) -> Result<(), io::Error> {
+        let parent = self.target.parent().ok_or(io::ErrorKind::InvalidInput)?;
+        match &self.old {
+            Some(old) if exchange(old, &self.target).is_ok() => {
+                sync_dir(parent)?;
+                let _ = fs::remove_dir_all(old);
+            }
+            Some(old) => {
+                fs::remove_dir_all(&self.target)?;
+                fs::rename(old, &self.target)?;
+                sync_dir(parent)?;
+            }
+            None => fs::remove_dir_all(&self.target)?,
+        }
+        Ok(())
+    }
+}
+
+/**
+# Usecase
+Like `swap_in`, but keeps the old contents of `target` aside, so a restore touching several directories can put
+every one of them back if a later swap fails.
+*/
+pub fn swap_keeping_old(staging: &Path, target: &Path) -> Result<Swapped, io::Error> {
+    let parent = target.parent().ok_or(io::ErrorKind::InvalidInput)?;
+    let old = if !target.exists() {
+        fs::rename(staging, target)?;
+        None
+    } else if exchange(staging, target).is_ok() {
+        // NOTE: staging now holds the old save
+        Some(staging.to_path_buf())
+    } else {
+        let old = sibling_path(target, "old")?;
+        if old.exists() {
+            fs::remove_dir_all(&old)?;
+        }
+        fs::rename(target, &old)?;
+        if let Err(err) = fs::rename(staging, target) {
+            fs::rename(&old, target)?;
+            return Err(err);
+        }
+        Some(old)
+    };
+    let swapped = Swapped {
+        target: target.to_path_buf(),
+        old,
+    };
+    if let Err(err) = sync_dir(parent) {
+        let _ = swapped.roll_back();
+        return Err(err);
+    }
+    Ok(swapped)
+}
@@ -0,0 +1,145 @@
+use crate::config::{game::Game, mirror, save::Save, save_conf};
+use crate::settings::{RetentionPolicy, Settings};
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashSet},
+    io,
+    path::Path,
+};
+use ulid::Ulid;
+
+/// Maps a timestamp to the day, ISO week or month it falls in.
+type PeriodOf = fn(&NaiveDateTime) -> (i32, u32, u32);
+
+/// What the retention policy needs to know about one snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub id: Ulid,
+    pub saved_at: DateTime<Utc>,
+    /// `saved_at` in the configured timezone, which decides the day, week and month a snapshot counts for.
+    pub local: NaiveDateTime,
+    pub pinned: bool,
+    pub bytes: u64,
+}
+
+/// Outcome of pruning one game. With a dry run nothing in `pruned` has been deleted yet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    pub game_title: String,
+    pub kept: Vec<Ulid>,
+    pub pruned: Vec<Ulid>,
+    pub freed_bytes: u64,
+}
+
+/**
+# Usecase
+Applies `policy` to `candidates` and returns the IDs of the snapshots to keep.
+Periods are counted like borg and restic do: `keep_daily: 7` keeps the newest snapshot of each of the
+last seven days that have a snapshot, not of the last seven calendar days.
+*/
+pub fn select_kept(candidates: &[Candidate], policy: &RetentionPolicy) -> BTreeSet<Ulid> {
+    let mut newest_first: Vec<&Candidate> = candidates.iter().collect();
+    newest_first.sort_by_key(|candidate| Reverse((candidate.saved_at, candidate.id)));
+    let has_rules = policy.keep_last.is_some()
+        || policy.keep_daily.is_some()
+        || policy.keep_weekly.is_some()
+        || policy.keep_monthly.is_some();
+
+    let mut kept: BTreeSet<Ulid> = candidates
+        .iter()
+        .filter(|candidate| !has_rules || candidate.pinned)
+        .map(|candidate| candidate.id)
+        .collect();
+    let keep_last = policy.keep_last.unwrap_or(0) as usize;
+    kept.extend(
+        newest_first
+            .iter()
+            .take(keep_last)
+            .map(|candidate| candidate.id),
+    );
+
+    let periods: [(Option<u32>, PeriodOf); 3] = [
+        (policy.keep_daily, |at| (at.year(), at.month(), at.day())),
+        (policy.keep_weekly, |at| {
+            (at.iso_week().year(), at.iso_week().week(), 0)
+        }),
+        (policy.keep_monthly, |at| (at.year(), at.month(), 0)),
+    ];
+    for (limit, period_of) in periods {
+        let Some(limit) = limit else { continue };
+        let mut seen = HashSet::new();
+        for candidate in &newest_first {
+            if seen.len() == limit as usize {
+                break;
+            }
+            if seen.insert(period_of(&candidate.local)) {
+                kept.insert(candidate.id);
+            }
+        }
+    }
+
+    if let Some(max_bytes) = policy.max_bytes {
+        let newest = newest_first.first().map(|candidate| candidate.id);
+        let mut total: u64 = candidates
+            .iter()
+            .filter(|candidate| kept.contains(&candidate.id))
+            .map(|candidate| candidate.bytes)
+            .sum();
+        for candidate in newest_first.iter().rev() {
+            if total <= max_bytes {
+                break;
+            }
+            if candidate.pinned || Some(candidate.id) == newest || !kept.contains(&candidate.id) {
+                continue;
+            }
+            kept.remove(&candidate.id);
+            total -= candidate.bytes;
+        }
+    }
+    kept
+}
+
+/**
+# Usecase
+Prunes every game in the catalog according to its retention policy.
+The catalog at `conf_path` is rewritten before anything is deleted, so a failure part way through can only leave
+unreferenced snapshot directories behind, never entries pointing at snapshots that are gone.
+With `dry_run`, only the reports are built.
+*/
+pub fn prune(
+    games: &mut [Game],
+    settings: &Settings,
+    conf_path: &Path,
+    dry_run: bool,
+) -> Result<Vec<PruneReport>, io::Error> {
+    let reports = games
+        .iter()
+        .map(|game| game.plan_prune(settings))
+        .collect::<Result<Vec<_>, _>>()?;
+    if dry_run || reports.iter().all(|report| report.pruned.is_empty()) {
+        return Ok(reports);
+    }
+    let doomed: Vec<Save> = games
+        .iter_mut()
+        .zip(&reports)
+        .flat_map(|(game, report)| game.take_saves(&report.pruned))
+        .collect();
+    save_conf(games, conf_path)?;
+    // NOTE: Keep going past a failed delete, whatever is left over is merely unreferenced
+    let mut result = Ok(reports);
+    let mut deleted = Vec::new();
+    for save in doomed {
+        match save.delete(settings) {
+            Ok(()) => deleted.push(save),
+            Err(err) => {
+                eprintln!("Could not delete {:?} due to {}", save.backup_path, err);
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+    }
+    mirror::forget(&deleted, settings);
+    result
+}
@@ -0,0 +1,168 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/**
+# Usecase
+"Smart mode": which files below a save directory get backed up and restored.
+
+Globs and regexes are matched against the path relative to the save directory, with `/` as
+separator (e.g. `saves/slot_1.sav`). A file is selected when no include rule is given or
+any include rule matches, and no exclude rule matches.
+
+# Example
+```
+use oxi::config::rules::Rules;
+use std::path::Path;
+
+let rules = Rules {
+    include: vec!["*.sav".to_string()],
+    exclude_regex: vec![r"^backup_\d+".to_string()],
+    ..Rules::default()
+};
+let matcher = rules.compile().expect("valid rules");
+assert!(matcher.is_selected(Path::new("slot_1.sav")));
+assert!(!matcher.is_selected(Path::new("backup_12.sav")));
+assert!(!matcher.is_selected(Path::new("options.ini")));
+```
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Rules {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub include_regex: Vec<String>,
+    #[serde(default)]
+    pub exclude_regex: Vec<String>,
+}
+
+impl Rules {
+    /// Excludes for what Wine/Proton and common engines dump into a prefix next to the actual saves.
+    pub fn proton_defaults() -> Rules {
+        Rules {
+            exclude: [
+                "**/*.dmp",
+                "**/*.mdmp",
+                "**/*.log",
+                "**/*.log.*",
+                "**/CrashDumps/**",
+                "**/CrashReportClient/**",
+                "**/Crashes/**",
+                "**/ShaderCache/**",
+                "**/shadercache/**",
+                "**/shader_cache/**",
+                "**/DerivedDataCache/**",
+                "**/GPUCache/**",
+                "**/Temp/**",
+                "**/webcache/**",
+                "**/*.tmp",
+            ]
+            .iter()
+            .map(|glob| glob.to_string())
+            .collect(),
+            ..Rules::default()
+        }
+    }
+
+    /// The rules to use for a save directory when its game has none: the Proton defaults
+    /// for anything inside a `compatdata/<id>/pfx` prefix, nothing otherwise.
+    pub fn defaults_for(production_path: &Path) -> Option<Rules> {
+        let components: Vec<Component> = production_path.components().collect();
+        components
+            .windows(3)
+            .any(|window| window[0].as_os_str() == "compatdata" && window[2].as_os_str() == "pfx")
+            .then(Rules::proton_defaults)
+    }
+
+    pub fn compile(&self) -> io::Result<Matcher> {
+        let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidInput, err);
+        let globs = |patterns: &[String]| -> io::Result<GlobSet> {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in patterns {
+                builder.add(Glob::new(pattern).map_err(|err| invalid(err.to_string()))?);
+            }
+            builder.build().map_err(|err| invalid(err.to_string()))
+        };
+        let regexes =
+            |patterns: &[String]| RegexSet::new(patterns).map_err(|err| invalid(err.to_string()));
+        Ok(Matcher {
+            select_all: self.include.is_empty() && self.include_regex.is_empty(),
+            include: globs(&self.include)?,
+            exclude: globs(&self.exclude)?,
+            include_regex: regexes(&self.include_regex)?,
+            exclude_regex: regexes(&self.exclude_regex)?,
+        })
+    }
+}
+
+/**
+# Usecase
+Which files of a snapshot a selective restore writes back, relative to the save directory like `Rules`.
+Each of `paths` names a file or a whole directory; `globs` use the same syntax as `Rules`.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    pub paths: Vec<PathBuf>,
+    pub globs: Vec<String>,
+}
+
+impl Selection {
+    pub fn compile(&self) -> io::Result<Matcher> {
+        if self.paths.is_empty() && self.globs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "nothing selected to restore",
+            ));
+        }
+        let mut include = self.globs.clone();
+        for path in &self.paths {
+            let escaped = globset::escape(&slash_path(path));
+            include.push(format!("{}/**", escaped));
+            include.push(escaped);
+        }
+        Rules {
+            include,
+            ..Rules::default()
+        }
+        .compile()
+    }
+}
+
+/// `relative` with `/` as separator on every platform, which is what the patterns are written against.
+fn slash_path(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Compiled form of `Rules`.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    select_all: bool,
+    include: GlobSet,
+    exclude: GlobSet,
+    include_regex: RegexSet,
+    exclude_regex: RegexSet,
+}
+
+impl Matcher {
+    pub fn is_selected(&self, relative: &Path) -> bool {
+        let text = slash_path(relative);
+        let included =
+            self.select_all || self.include.is_match(&text) || self.include_regex.is_match(&text);
+        included && !self.exclude.is_match(&text) && !self.exclude_regex.is_match(&text)
+    }
+
+    /// Splits `files` into those the rules select and those they leave alone.
+    pub fn partition(&self, files: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        files.into_iter().partition(|path| self.is_selected(path))
+    }
+}
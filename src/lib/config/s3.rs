@@ -0,0 +1,363 @@
+use crate::config::backend::{check_key, ObjectKind, StorageBackend};
+use chrono::Utc;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+    time::Duration,
+};
+
+/// How long a single request may take before it is given up on.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/**
+# Usecase
+A backend in an S3-compatible object store: AWS S3, MinIO, Backblaze B2, Cloudflare R2, Garage and the like.
+Objects are addressed path-style, `<endpoint>/<bucket>/<prefix><blobs|manifests>/<key>`, which every
+S3-compatible service understands, and requests are signed with AWS Signature Version 4.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Backend {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or `http://127.0.0.1:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    /// Put in front of every object name, e.g. `oxi/` to share a bucket.
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// What a request sends: bytes already in memory, or a file that is streamed from disk.
+#[derive(Debug, Clone, Copy)]
+enum Payload<'a> {
+    Bytes(&'a [u8]),
+    File(&'a Path),
+}
+
+impl Payload<'_> {
+    /// The hex SHA-256 the request is signed with. A file is read once for it before it is sent.
+    fn sha256(&self) -> io::Result<String> {
+        match self {
+            Payload::Bytes(data) => Ok(hex_sha256(data)),
+            Payload::File(path) => {
+                let mut hasher = Sha256::new();
+                io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+                Ok(hex::encode(hasher.finalize()))
+            }
+        }
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    match key.len() > block.len() {
+        true => block[..32].copy_from_slice(&Sha256::digest(key)),
+        false => block[..key.len()].copy_from_slice(key),
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// Percent-encodes everything but the characters S3 leaves alone, and `/` too unless `keep_slash`.
+fn uri_encode(text: &str, keep_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// One request to be signed. `headers` must include `host`, `x-amz-date` and `x-amz-content-sha256`.
+#[derive(Debug, Clone)]
+pub struct SigningRequest<'a> {
+    pub method: &'a str,
+    /// Already URI-encoded, e.g. `/bucket/key`.
+    pub path: &'a str,
+    pub query: &'a [(String, String)],
+    pub headers: &'a BTreeMap<String, String>,
+}
+
+/**
+# Usecase
+The `Authorization` header for `request` under AWS Signature Version 4. Every header in `request.headers` is
+signed; their names have to be lowercase.
+*/
+pub fn authorization(
+    request: &SigningRequest,
+    region: &str,
+    access_key: &str,
+    secret_key: &str,
+) -> String {
+    let amz_date = request
+        .headers
+        .get("x-amz-date")
+        .map(String::as_str)
+        .unwrap_or_default();
+    let payload_hash = request
+        .headers
+        .get("x-amz-content-sha256")
+        .map(String::as_str)
+        .unwrap_or_default();
+    let date = amz_date.get(..8).unwrap_or_default();
+    let mut query: Vec<(String, String)> = request
+        .query
+        .iter()
+        .map(|(key, value)| (uri_encode(key, false), uri_encode(value, false)))
+        .collect();
+    query.sort();
+    let canonical_query = query
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("&");
+    let canonical_headers: String = request
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = request
+        .headers
+        .keys()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method,
+        request.path,
+        canonical_query,
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex_sha256(canonical_request.as_bytes())
+    );
+    let key = [region, "s3", "aws4_request"].iter().fold(
+        hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes()),
+        |key, part| hmac_sha256(&key, part.as_bytes()),
+    );
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key,
+        scope,
+        signed_headers,
+        hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()))
+    )
+}
+
+fn to_io_error(err: ureq::Error) -> io::Error {
+    match err {
+        ureq::Error::Status(404, _) => io::ErrorKind::NotFound.into(),
+        ureq::Error::Status(403, response) => io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("access denied by {}", response.get_url()),
+        ),
+        err => io::Error::other(err),
+    }
+}
+
+impl S3Backend {
+    /// The `Host` header the request will carry: the endpoint's authority, without a port the scheme implies.
+    fn host(&self) -> String {
+        let (scheme, rest) = self
+            .endpoint
+            .split_once("://")
+            .unwrap_or(("https", &self.endpoint));
+        let authority = rest.split('/').next().unwrap_or_default();
+        let default_port = match scheme {
+            "http" => ":80",
+            _ => ":443",
+        };
+        authority
+            .strip_suffix(default_port)
+            .unwrap_or(authority)
+            .to_string()
+    }
+
+    fn object_name(&self, kind: ObjectKind, key: &str) -> String {
+        match kind {
+            ObjectKind::Blob => format!("{}blobs/{}/{}", self.prefix, &key[..2], key),
+            ObjectKind::Manifest => format!("{}manifests/{}", self.prefix, key),
+        }
+    }
+
+    /// Sends a signed request for `object` (empty for the bucket itself).
+    fn send(
+        &self,
+        method: &str,
+        object: &str,
+        query: &[(String, String)],
+        body: &[u8],
+    ) -> io::Result<ureq::Response> {
+        self.send_payload(method, object, query, Payload::Bytes(body))
+    }
+
+    /// Like `send`, for a body that may be streamed from a file.
+    fn send_payload(
+        &self,
+        method: &str,
+        object: &str,
+        query: &[(String, String)],
+        payload: Payload,
+    ) -> io::Result<ureq::Response> {
+        let path = format!(
+            "/{}/{}",
+            uri_encode(&self.bucket, false),
+            uri_encode(object, true)
+        );
+        let mut headers = BTreeMap::new();
+        headers.insert("host".to_string(), self.host());
+        headers.insert("x-amz-content-sha256".to_string(), payload.sha256()?);
+        headers.insert(
+            "x-amz-date".to_string(),
+            Utc::now().format("%Y%m%dT%H%M%SZ").to_string(),
+        );
+        let signing = SigningRequest {
+            method,
+            path: &path,
+            query,
+            headers: &headers,
+        };
+        let signature = authorization(&signing, &self.region, &self.access_key, &self.secret_key);
+        // NOTE: Encoded here rather than by ureq, so the query sent is exactly the one signed
+        let query_string = query
+            .iter()
+            .map(|(key, value)| format!("{}={}", uri_encode(key, false), uri_encode(value, false)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let url = match query_string.is_empty() {
+            true => format!("{}{}", self.endpoint.trim_end_matches('/'), path),
+            false => format!(
+                "{}{}?{}",
+                self.endpoint.trim_end_matches('/'),
+                path,
+                query_string
+            ),
+        };
+        let mut request = ureq::request(method, &url)
+            .timeout(TIMEOUT)
+            .set("Authorization", &signature);
+        for (name, value) in &headers {
+            if name != "host" {
+                request = request.set(name, value);
+            }
+        }
+        match (method, payload) {
+            ("PUT", Payload::Bytes(body)) => request.send_bytes(body),
+            (_, Payload::File(path)) => {
+                let file = File::open(path)?;
+                // NOTE: With the length given up front ureq sends the file as it is read, not chunked
+                let length = file.metadata()?.len();
+                request
+                    .set("Content-Length", &length.to_string())
+                    .send(BufReader::new(file))
+            }
+            _ => request.call(),
+        }
+        .map_err(to_io_error)
+    }
+}
+
+impl StorageBackend for S3Backend {
+    fn put(&self, kind: ObjectKind, key: &str, data: &[u8]) -> io::Result<()> {
+        check_key(kind, key)?;
+        self.send("PUT", &self.object_name(kind, key), &[], data)
+            .map(|_| ())
+    }
+
+    fn put_file(&self, kind: ObjectKind, key: &str, path: &Path) -> io::Result<u64> {
+        check_key(kind, key)?;
+        let length = path.metadata()?.len();
+        self.send_payload(
+            "PUT",
+            &self.object_name(kind, key),
+            &[],
+            Payload::File(path),
+        )?;
+        Ok(length)
+    }
+
+    fn get(&self, kind: ObjectKind, key: &str) -> io::Result<Vec<u8>> {
+        check_key(kind, key)?;
+        let response = self.send("GET", &self.object_name(kind, key), &[], &[])?;
+        let mut data = Vec::new();
+        response.into_reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Pages through ListObjectsV2, 1000 names at a time.
+    fn list(&self, kind: ObjectKind) -> io::Result<Vec<String>> {
+        let prefix = format!("{}{}/", self.prefix, kind.dir());
+        let key_pattern = Regex::new("<Key>([^<]*)</Key>").map_err(io::Error::other)?;
+        let token_pattern = Regex::new("<NextContinuationToken>([^<]*)</NextContinuationToken>")
+            .map_err(io::Error::other)?;
+        let mut keys = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("list-type".to_string(), "2".to_string()),
+                ("prefix".to_string(), prefix.clone()),
+            ];
+            if let Some(token) = &token {
+                query.push(("continuation-token".to_string(), token.clone()));
+            }
+            let body = self
+                .send("GET", "", &query, &[])?
+                .into_string()
+                .map_err(io::Error::other)?;
+            keys.extend(key_pattern.captures_iter(&body).filter_map(|captures| {
+                let name = captures.get(1)?.as_str();
+                name.rsplit('/').next().map(String::from)
+            }));
+            token = token_pattern
+                .captures(&body)
+                .and_then(|captures| captures.get(1))
+                .map(|token| token.as_str().replace("&amp;", "&"));
+            if token.is_none() || !body.contains("<IsTruncated>true</IsTruncated>") {
+                return Ok(keys);
+            }
+        }
+    }
+
+    fn delete(&self, kind: ObjectKind, key: &str) -> io::Result<()> {
+        check_key(kind, key)?;
+        // NOTE: S3 answers 204 for keys that never existed, some compatible stores 404
+        match self.send("DELETE", &self.object_name(kind, key), &[], &[]) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn contains(&self, kind: ObjectKind, key: &str) -> io::Result<bool> {
+        check_key(kind, key)?;
+        match self.send("HEAD", &self.object_name(kind, key), &[], &[]) {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
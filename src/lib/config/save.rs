@@ -1,74 +1,857 @@
+use crate::config::timestamps;
+use crate::config::{
+    archive, check_plain_name,
+    copy::{copy_files, copy_tree},
+    linked,
+    manifest::Manifest,
+    mirror::{self, MirrorState},
+    restore,
+    rules::{Rules, Selection},
+    sidecar,
+    store::Store,
+    verify::VerifyReport,
+    walk_files,
+};
+use crate::settings::{Settings, StorageMode};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::{path::PathBuf, process::exit};
-extern crate fs_extra;
-use fs_extra::dir::{copy, CopyOptions};
 use std::time::Instant;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+use ulid::Ulid;
 
 use super::test_create_dir;
 
+/// A restored copy of one live directory, written next to it and waiting to be swapped in.
+struct Staged {
+    target: PathBuf,
+    staging: PathBuf,
+    summary: String,
+}
+
+/// Directory inside `backup_path` holding one sub-snapshot per additional `SaveRoot`.
+pub const ROOTS_DIR: &str = "roots";
+
+/// Identifies a file on disk regardless of the names hardlinked to it.
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Names the save directory cannot have for plain copies, `data_path` would land on the snapshot's own files.
+const RESERVED_DATA_NAMES: [&str; 3] = [ROOTS_DIR, "manifest.json", sidecar::SIDECAR_NAME];
+
+fn default_restore() -> bool {
+    true
+}
+
+/**
+# Usecase
+An additional, named location a game keeps state in, e.g. settings in Documents or keybinds in the install directory.
+Every root is captured into the same snapshot as `production_path` and restored back to its own `path`.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SaveRoot {
+    /// Used as the directory name inside the snapshot, so it has to be a plain file name.
+    pub name: String,
+    pub path: PathBuf,
+    /// Set to `false` to back the root up but never write it back.
+    #[serde(default = "default_restore")]
+    pub restore: bool,
+    /// Smart mode rules for this root. Falls back to `Rules::defaults_for`.
+    #[serde(default)]
+    pub rules: Option<Rules>,
+}
+
 #[allow(dead_code)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Save {
-    // u8 Should be enough, but testing easily surpases this lol
+    /// Stable, globally unique and time-sortable. Catalogs from before IDs read as nil until `Game::migrate_ids` runs.
+    #[serde(default)]
+    pub id: Ulid,
+    /// Display ordinal only: the n-th snapshot taken of the game. Snapshots are looked up by `id`.
     pub count: u16,
     pub backup_path: PathBuf,
     pub production_path: PathBuf,
     pub parent_game: String,
-    pub saved_at: String,
+    /// Steam app ID of `parent_game`, recorded in the sidecar so the catalog can be rebuilt from the store.
+    #[serde(default)]
+    pub game_id: u32,
+    /// When the snapshot was taken. Use `Settings::format_time` to show it.
+    #[serde(with = "timestamps::legacy")]
+    pub saved_at: DateTime<Utc>,
+    #[serde(default)]
+    pub storage: StorageMode,
+    /// For `StorageMode::Linked`: the data directory of the snapshot to hardlink unchanged files against.
+    #[serde(default)]
+    pub link_dest: Option<PathBuf>,
+    /// Set on the automatic snapshot taken before restoring the save with this ID.
+    #[serde(default)]
+    pub pre_restore_of: Option<Ulid>,
+    /// Smart mode rules this snapshot was taken with. `None` captures the whole directory.
+    #[serde(default)]
+    pub rules: Option<Rules>,
+    /// Further locations captured together with `production_path`.
+    #[serde(default)]
+    pub roots: Vec<SaveRoot>,
+    /// Pinned snapshots are never pruned.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Short name shown in place of the count, e.g. "before Malenia phase 2".
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Sync state with each of `Settings::destinations`, by name.
+    #[serde(default)]
+    pub mirrors: BTreeMap<String, MirrorState>,
 }
 impl Save {
-    #[allow(dead_code)]
-    pub fn backup(&mut self) {
-        let mut coptions = CopyOptions::new();
-        coptions.overwrite = true;
-        coptions.copy_inside = true;
+    /// Location of the snapshot's manifest. For dedup snapshots it is the only thing kept in `backup_path`.
+    pub fn manifest_path(&self) -> PathBuf {
+        self.backup_path.join("manifest.json")
+    }
+
+    /// Location of the compressed tarball for snapshots kept as archives.
+    pub fn archive_path(&self) -> PathBuf {
+        self.backup_path.join(archive::ARCHIVE_NAME)
+    }
+
+    /// Where `Directory` and `Linked` snapshots keep their copy of the production directory.
+    pub fn data_path(&self) -> PathBuf {
+        match self.production_path.file_name() {
+            Some(name) => self.backup_path.join(name),
+            None => self.backup_path.clone(),
+        }
+    }
+
+    /**
+    # Usecase
+    The part of this snapshot holding each additional root, as a `Save` of its own kept in
+    `backup_path/roots/<name>`. Every other method handles a root by delegating to its part.
+    */
+    pub fn root_saves(&self) -> Result<Vec<(&SaveRoot, Save)>, io::Error> {
+        let mut names = HashSet::new();
+        self.roots
+            .iter()
+            .map(|root| {
+                check_plain_name(&root.name)?;
+                // NOTE: Two roots with one name would be written into, and restored from, the same directory
+                if !names.insert(root.name.as_str()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("more than one save root is named {}", root.name),
+                    ));
+                }
+                let backup_path = self.backup_path.join(ROOTS_DIR).join(&root.name);
+                // NOTE: Link against the same root in the previous snapshot
+                let link_dest = self
+                    .link_dest
+                    .as_ref()
+                    .and_then(|previous| previous.parent())
+                    .map(|previous| {
+                        let previous = previous.join(ROOTS_DIR).join(&root.name);
+                        match root.path.file_name() {
+                            Some(name) => previous.join(name),
+                            None => previous,
+                        }
+                    });
+                let part = Save {
+                    backup_path,
+                    production_path: root.path.clone(),
+                    link_dest,
+                    rules: root
+                        .rules
+                        .clone()
+                        .or_else(|| Rules::defaults_for(&root.path)),
+                    roots: Vec::new(),
+                    ..self.clone()
+                };
+                Ok((root, part))
+            })
+            .collect()
+    }
+
+    /**
+    # Usecase
+    Copies `production_path` into this snapshot using its `storage` mode, then mirrors it to the configured
+    destinations. Prints a summary either way and hands the error back so callers can stop on a failed backup;
+    a destination that cannot be reached is only recorded in `mirrors`.
+    */
+    pub fn backup(&mut self, settings: &Settings) -> Result<(), io::Error> {
         let start = Instant::now();
         if let Err(err) = test_create_dir(&self.backup_path) {
             eprintln!("Could not create path for backing up due to {}", err);
-            exit(1);
-        }
-        match copy(
-            self.production_path.clone(),
-            self.backup_path.clone(),
-            &coptions,
-        ) {
-            Ok(_) => println!(
-                "\x1b[32mSuccessfully backed up \x1b[34m{}\x1b[0m in \x1b[36m{:.2?}",
+            return Err(err);
+        }
+        let result = self.backup_part(settings).and_then(|summary| {
+            let mut summaries = vec![summary];
+            for (root, part) in self.root_saves()? {
+                fs::create_dir_all(&part.backup_path)?;
+                summaries.push(format!("{}: {}", root.name, part.backup_part(settings)?));
+            }
+            sidecar::write(self, settings)?;
+            Ok(summaries.join(", "))
+        });
+        match &result {
+            Ok(summary) => println!(
+                "\x1b[32mSuccessfully backed up \x1b[34m{}\x1b[0m at \x1b[35m{}\x1b[0m ({}) in \x1b[36m{:.2?}\x1b[0m",
                 self.parent_game,
+                settings.format_time(self.saved_at),
+                summary,
                 start.elapsed()
             ),
-            Err(err) => eprintln!(
-                "Failed to back up {} due to {:?}",
-                self.parent_game, err.kind
+            Err(err) => eprintln!("Failed to back up {} due to {}", self.parent_game, err),
+        }
+        if result.is_ok() {
+            mirror::mirror_save(self, settings);
+        }
+        result.map(|_| ())
+    }
+
+    /// Refuses plain copies whose `data_path` would clash with the manifest, sidecar or roots of the snapshot.
+    fn check_data_path(&self) -> Result<(), io::Error> {
+        if !matches!(self.storage, StorageMode::Directory | StorageMode::Linked) {
+            return Ok(());
+        }
+        match self.production_path.file_name() {
+            Some(name) if !RESERVED_DATA_NAMES.iter().any(|reserved| name == *reserved) => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{:?} clashes with the snapshot's own files when copied as is, use dedup or archive storage for it",
+                    self.production_path
+                ),
+            )),
+        }
+    }
+
+    /// Backs up `production_path` alone, leaving the additional roots to the caller.
+    fn backup_part(&self, settings: &Settings) -> Result<String, io::Error> {
+        self.check_data_path()?;
+        let (selected, _) = self.partition_live_files()?;
+        // NOTE: Hashed before copying, so a file damaged or changed on the way does not match below
+        let source = match self.storage {
+            StorageMode::Dedup => None,
+            _ => Some(Manifest::from_files(&self.production_path, &selected)?),
+        };
+        let summary = match self.storage {
+            StorageMode::Directory => self.backup_directory(&selected),
+            StorageMode::Dedup => self.backup_dedup(&selected, settings),
+            StorageMode::Archive { level } => self.backup_archive(&selected, level),
+            StorageMode::Linked => self.backup_linked(&selected),
+        }?;
+        if let Some(source) = source {
+            self.record_source_manifest(source)?;
+        }
+        Ok(summary)
+    }
+
+    /// What the snapshot holds on disk, for modes whose manifest is not written by the store.
+    fn stored_manifest(&self) -> Result<Option<Manifest>, io::Error> {
+        match self.storage {
+            StorageMode::Dedup => Ok(None),
+            StorageMode::Directory | StorageMode::Linked => {
+                Manifest::from_dir(&self.data_path()).map(Some)
+            }
+            StorageMode::Archive { .. } => archive::manifest(&self.archive_path()).map(Some),
+        }
+    }
+
+    /**
+    # Usecase
+    Records `source`, the hashes taken from the live files, as the manifest, after checking the finished copy
+    against it. A copy that came out different fails the backup instead of being recorded as correct.
+    */
+    fn record_source_manifest(&self, source: Manifest) -> Result<(), io::Error> {
+        let stored = self.stored_manifest()?.unwrap_or_default();
+        let report = VerifyReport::compare(&source, &stored);
+        if !report.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the copy does not match the live save: {} missing, {} extra, {} different",
+                    report.missing.len(),
+                    report.extra.len(),
+                    report.corrupted.len()
+                ),
+            ));
+        }
+        source.save(&self.manifest_path())
+    }
+
+    /// Writes the manifest for modes that do not produce one as part of the backup itself, from the snapshot as
+    /// it is now stored. For accepting a snapshot that changed on disk; backups record the live hashes instead.
+    pub(crate) fn record_manifest(&self) -> Result<(), io::Error> {
+        match self.stored_manifest()? {
+            Some(manifest) => manifest.save(&self.manifest_path()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn read_manifest(&self, settings: &Settings) -> Result<Manifest, io::Error> {
+        match self.storage {
+            StorageMode::Dedup => Store::open_with(settings)?.read_manifest(&self.manifest_path()),
+            _ => Manifest::load(&self.manifest_path()),
+        }
+    }
+
+    /**
+    # Usecase
+    How many bytes the snapshot takes up, as counted towards `RetentionPolicy.max_bytes`.
+    Dedup snapshots count the full size of the files they reference, since their blobs may be shared.
+    */
+    pub fn stored_bytes(&self, settings: &Settings) -> Result<u64, io::Error> {
+        self.stored_bytes_unseen(settings, &mut HashSet::new())
+    }
+
+    /**
+    # Usecase
+    Like `stored_bytes`, but files already in `seen` are not counted again, and the ones counted are added to it.
+    Hardlinked files, as `StorageMode::Linked` snapshots share them, then count once across all the snapshots
+    measured with the same `seen`.
+    */
+    pub fn stored_bytes_unseen(
+        &self,
+        settings: &Settings,
+        seen: &mut HashSet<(u64, u64)>,
+    ) -> Result<u64, io::Error> {
+        if self.storage == StorageMode::Dedup {
+            return Ok(self.snapshot_manifest(settings)?.total_size());
+        }
+        let mut bytes = 0;
+        for path in walk_files(&self.backup_path)? {
+            let metadata = fs::metadata(self.backup_path.join(path))?;
+            if file_id(&metadata).is_none_or(|id| seen.insert(id)) {
+                bytes += metadata.len();
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// The manifest of the whole snapshot, with the files of additional roots under `roots/<name>/`.
+    pub fn snapshot_manifest(&self, settings: &Settings) -> Result<Manifest, io::Error> {
+        let mut manifest = self.read_manifest(settings)?;
+        for (root, part) in self.root_saves()? {
+            manifest.absorb(
+                part.read_manifest(settings)?,
+                &Path::new(ROOTS_DIR).join(&root.name),
+            );
+        }
+        Ok(manifest)
+    }
+
+    /**
+    # Usecase
+    A manifest of what this snapshot would capture if it were taken now, laid out like `snapshot_manifest`
+    so the two can be compared. Live directories that do not exist count as empty.
+    */
+    pub fn live_manifest(&self, settings: &Settings) -> Result<Manifest, io::Error> {
+        let mut manifest = self.live_manifest_part(settings)?;
+        for (root, part) in self.root_saves()? {
+            manifest.absorb(
+                part.live_manifest_part(settings)?,
+                &Path::new(ROOTS_DIR).join(&root.name),
+            );
+        }
+        Ok(manifest)
+    }
+
+    fn live_manifest_part(&self, settings: &Settings) -> Result<Manifest, io::Error> {
+        if !self.production_path.exists() {
+            return Ok(Manifest::default());
+        }
+        let (selected, _) = self.partition_live_files()?;
+        match self.storage {
+            // NOTE: Dedup manifests may use keyed hashes, so the live files have to be hashed the same way
+            StorageMode::Dedup => {
+                Store::open_with(settings)?.manifest_of_files(&self.production_path, &selected)
+            }
+            _ => Manifest::from_files(&self.production_path, &selected),
+        }
+    }
+
+    /// Where a path from `snapshot_manifest` or `live_manifest` lives on disk right now.
+    pub fn live_path(&self, relative: &Path) -> PathBuf {
+        let root = self.roots.iter().find_map(|root| {
+            relative
+                .strip_prefix(Path::new(ROOTS_DIR).join(&root.name))
+                .ok()
+                .map(|rest| root.path.join(rest))
+        });
+        root.unwrap_or_else(|| self.production_path.join(relative))
+    }
+
+    /// Writes the whole snapshot into `dst` laid out like `snapshot_manifest`. Nothing live is touched.
+    pub fn extract_to(&self, dst: &Path, settings: &Settings) -> Result<(), io::Error> {
+        fs::create_dir_all(dst)?;
+        self.restore_into(dst, settings)?;
+        for (root, part) in self.root_saves()? {
+            let root_dst = dst.join(ROOTS_DIR).join(&root.name);
+            fs::create_dir_all(&root_dst)?;
+            part.restore_into(&root_dst, settings)?;
+        }
+        Ok(())
+    }
+
+    /**
+    # Usecase
+    Checks the snapshot against the manifest written when it was taken and reports missing,
+    extra and corrupted files. Dedup snapshots have each blob re-hashed (and authenticated, if encrypted).
+    */
+    pub fn verify(&self, settings: &Settings) -> Result<VerifyReport, io::Error> {
+        let mut report = self.verify_part(settings)?;
+        for (root, part) in self.root_saves()? {
+            report.absorb(
+                part.verify_part(settings)?,
+                &Path::new(ROOTS_DIR).join(&root.name),
+            );
+        }
+        Ok(report)
+    }
+
+    fn verify_part(&self, settings: &Settings) -> Result<VerifyReport, io::Error> {
+        let expected = self.read_manifest(settings)?;
+        match self.storage {
+            StorageMode::Directory | StorageMode::Linked => Ok(VerifyReport::compare(
+                &expected,
+                &Manifest::from_dir(&self.data_path())?,
+            )),
+            StorageMode::Archive { .. } => match archive::manifest(&self.archive_path()) {
+                Ok(actual) => Ok(VerifyReport::compare(&expected, &actual)),
+                // NOTE: An archive that cannot be read to the end has nothing we can trust
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Ok(VerifyReport {
+                    corrupted: expected.files.into_keys().collect(),
+                    ..VerifyReport::default()
+                }),
+                Err(_) => Ok(VerifyReport {
+                    missing: expected.files.into_keys().collect(),
+                    ..VerifyReport::default()
+                }),
+            },
+            StorageMode::Dedup => {
+                let store = Store::open_with(settings)?;
+                let mut report = VerifyReport::default();
+                for (path, entry) in expected.files {
+                    if !store.contains(&entry.hash) {
+                        report.missing.push(path);
+                        continue;
+                    }
+                    match store.check_blob(&entry.hash) {
+                        Ok(true) => {}
+                        Ok(false) => report.corrupted.push(path),
+                        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                            report.corrupted.push(path)
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(report)
+            }
+        }
+    }
+
+    /**
+    # Usecase
+    A restore drill: restores this snapshot into a scratch directory through the same staged
+    `restore_with` path a real restore takes, then checks the result against the manifest.
+    The live save is never touched.
+    */
+    pub fn drill(&self, settings: &Settings) -> Result<VerifyReport, io::Error> {
+        let mut report = self.drill_part(settings)?;
+        for (root, part) in self.root_saves()? {
+            report.absorb(
+                part.drill_part(settings)?,
+                &Path::new(ROOTS_DIR).join(&root.name),
+            );
+        }
+        Ok(report)
+    }
+
+    fn drill_part(&self, settings: &Settings) -> Result<VerifyReport, io::Error> {
+        let expected = self.read_manifest(settings)?;
+        let scratch = tempfile::tempdir()?;
+        let mut rehearsal = self.clone();
+        rehearsal.roots = Vec::new();
+        // NOTE: Keep the directory name, `data_path` depends on it
+        rehearsal.production_path = match self.production_path.file_name() {
+            Some(name) => scratch.path().join(name),
+            None => scratch.path().join("save"),
+        };
+        rehearsal.restore_with(settings, true)?;
+        let actual = match self.storage {
+            StorageMode::Dedup => {
+                Store::open_with(settings)?.manifest_of(&rehearsal.production_path)?
+            }
+            _ => Manifest::from_dir(&rehearsal.production_path)?,
+        };
+        Ok(VerifyReport::compare(&expected, &actual))
+    }
+
+    /**
+    # Usecase
+    Splits the live files into those this snapshot's rules select and those they leave alone,
+    both relative to `production_path`.
+    */
+    pub fn partition_live_files(&self) -> Result<(Vec<PathBuf>, Vec<PathBuf>), io::Error> {
+        let files = walk_files(&self.production_path)?;
+        match &self.rules {
+            Some(rules) => Ok(rules.compile()?.partition(files)),
+            None => Ok((files, Vec::new())),
+        }
+    }
+
+    fn backup_directory(&self, selected: &[PathBuf]) -> Result<String, io::Error> {
+        Ok(copy_files(&self.production_path, selected, &self.data_path())?.to_string())
+    }
+
+    fn backup_dedup(&self, selected: &[PathBuf], settings: &Settings) -> Result<String, io::Error> {
+        let store = Store::open_with(settings)?;
+        let manifest = store.snapshot(&self.production_path, selected)?;
+        store.write_manifest(&manifest, &self.manifest_path())?;
+        Ok(format!("{} files", manifest.files.len()))
+    }
+
+    fn backup_archive(&self, selected: &[PathBuf], level: i32) -> Result<String, io::Error> {
+        let size =
+            archive::write_archive(&self.production_path, selected, &self.archive_path(), level)?;
+        Ok(format!("{} bytes compressed", size))
+    }
+
+    fn backup_linked(&self, selected: &[PathBuf]) -> Result<String, io::Error> {
+        let previous = self.link_dest.as_deref().filter(|path| path.exists());
+        let report =
+            linked::link_snapshot(&self.production_path, selected, &self.data_path(), previous)?;
+        Ok(format!(
+            "{} linked, {} copied",
+            report.linked, report.copied
+        ))
+    }
+
+    /**
+    # Usecase
+    Writes this snapshot back over `production_path`.
+    The restored tree is built in a staging directory next to the live save, synced to disk and then
+    swapped in, so an interrupted restore never leaves a half-old, half-new save behind.
+    Honours `Settings.delete_on_restore`; see `restore_with` to choose explicitly.
+    This is the raw operation: `Game::restore_save` wraps it with a pre-restore safety snapshot that can be undone.
+    */
+    pub fn restore(&mut self, settings: &Settings) -> Result<(), io::Error> {
+        self.restore_with(settings, settings.delete_on_restore)
+    }
+
+    /**
+    # Usecase
+    Restores with an explicit choice between overlaying the snapshot onto the live save and
+    mirroring it, where files in `production_path` that are not in the snapshot are removed.
+    Files the snapshot's rules exclude are never removed, since the snapshot could not have held them, and neither
+    are symlinks or empty directories, which no snapshot holds.
+    Additional roots are restored along with it: all of them are staged before any is swapped in, and the ones
+    already swapped are put back if a later swap fails. Roots that opted out are skipped.
+    */
+    pub fn restore_with(&mut self, settings: &Settings, mirror: bool) -> Result<(), io::Error> {
+        self.restore_tracked(settings, mirror, &mut false)
+    }
+
+    /// Like `restore_with`, but sets `swapped` as soon as any part of the live save has been replaced, even if the
+    /// restore fails afterwards.
+    pub(crate) fn restore_tracked(
+        &self,
+        settings: &Settings,
+        mirror: bool,
+        swapped: &mut bool,
+    ) -> Result<(), io::Error> {
+        let start = Instant::now();
+        let result = self.restore_parts(settings, mirror, swapped);
+        match &result {
+            Ok(summary) => println!(
+                "\x1b[32mSuccessfully restored \x1b[34m{}\x1b[0m ({}) in \x1b[36m{:.2?}\x1b[0m",
+                self.parent_game,
+                summary,
+                start.elapsed()
             ),
+            Err(err) => eprintln!("Failed to restore {} due to {}", self.parent_game, err),
         }
+        result.map(|_| ())
     }
-    // TODO: Before overwriting the production_path, copy that to /tmp in case of errors
-    #[allow(dead_code)]
-    pub fn restore(&mut self) {
-        let mut coptions = CopyOptions::new();
-        coptions.overwrite = true;
-        coptions.content_only = true;
+
+    /**
+    # Usecase
+    Stages the live save and every root to restore before swapping any of them in, so a missing or broken part
+    leaves all of them untouched. If a swap still fails, the parts already swapped in are put back.
+    */
+    fn restore_parts(
+        &self,
+        settings: &Settings,
+        mirror: bool,
+        any_swapped: &mut bool,
+    ) -> Result<String, io::Error> {
+        let mut parts = vec![(None, self.clone())];
+        for (root, part) in self.root_saves()? {
+            if root.restore {
+                parts.push((Some(root.name.clone()), part));
+            }
+        }
+        let mut staged = Vec::new();
+        for (name, part) in &parts {
+            match part.stage(settings, mirror) {
+                Ok(stage) => staged.push((name, stage)),
+                Err(err) => {
+                    for (_, stage) in &staged {
+                        let _ = fs::remove_dir_all(&stage.staging);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        let mut swapped = Vec::new();
+        for (index, (_, stage)) in staged.iter().enumerate() {
+            match restore::swap_keeping_old(&stage.staging, &stage.target) {
+                Ok(done) => {
+                    *any_swapped = true;
+                    swapped.push(done);
+                }
+                Err(err) => {
+                    for (_, stage) in &staged[index..] {
+                        let _ = fs::remove_dir_all(&stage.staging);
+                    }
+                    for done in swapped.into_iter().rev() {
+                        if let Err(rollback) = done.roll_back() {
+                            eprintln!(
+                                "\x1b[31mCould not put back part of {} after a failed restore due to {}\x1b[0m",
+                                self.parent_game, rollback
+                            );
+                        }
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        swapped.into_iter().for_each(restore::Swapped::commit);
+        Ok(staged
+            .iter()
+            .map(|(name, stage)| match name {
+                Some(name) => format!("{}: {}", name, stage.summary),
+                None => stage.summary.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", "))
+    }
+
+    /// Writes the snapshot and the live files it keeps into a synced sibling of the live save, ready to swap in.
+    fn stage(&self, settings: &Settings, mirror: bool) -> Result<Staged, io::Error> {
+        let target = restore::swap_target(&self.production_path)?;
+        let staging = restore::sibling_path(&target, "staging")?;
+        // NOTE: Leftover from an interrupted restore. The live save was never touched by it
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        let result = (|| {
+            // NOTE: Mirroring starts from only the files the rules leave alone instead of the whole live save
+            if self.production_path.exists() {
+                let (selected, excluded) = self.partition_live_files()?;
+                let keep = match mirror {
+                    true => excluded,
+                    false => [selected, excluded].concat(),
+                };
+                copy_files(&self.production_path, &keep, &staging)?;
+            } else {
+                fs::create_dir_all(&staging)?;
+            }
+            let summary = self.restore_into(&staging, settings)?;
+            if target.exists() {
+                restore::carry_over_links(&target, &staging)?;
+            }
+            restore::sync_tree(&staging)?;
+            Ok(summary)
+        })();
+        match result {
+            Ok(summary) => Ok(Staged {
+                target,
+                staging,
+                summary,
+            }),
+            Err(err) => {
+                if staging.exists() {
+                    let _ = fs::remove_dir_all(&staging);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /**
+    # Usecase
+    Writes back only the files of this snapshot that `selection` picks, each replaced on its own through a synced
+    sibling file, so every other file in the live save is left exactly as it is.
+    With `mirror`, selected live files that are not in the snapshot are removed as well.
+    Returns the paths that were written or removed, laid out like `list_files`.
+    */
+    pub fn restore_selected(
+        &self,
+        settings: &Settings,
+        selection: &Selection,
+        mirror: bool,
+    ) -> Result<Vec<PathBuf>, io::Error> {
         let start = Instant::now();
-        if let Err(err) = test_create_dir(&self.backup_path) {
-            eprintln!("Could not create path for backing up due to {}", err);
-            exit(1);
-        }
-        if let Some(parent_path) = self.production_path.parent() {
-            match copy(self.backup_path.clone(), parent_path, &coptions) {
-                Ok(_) => println!(
-                    "\x1b[32mSuccessfully restored \x1b[34m{}\x1b[0m in \x1b[36m{:.2?}",
-                    self.parent_game,
-                    start.elapsed()
-                ),
-                Err(err) => eprintln!("Failed to restore {} due to {:?}", self.parent_game, err),
+        let result = (|| {
+            let matcher = selection.compile()?;
+            let scratch = tempfile::tempdir()?;
+            self.extract_to(scratch.path(), settings)?;
+            let restorable = |path: &Path| {
+                !self.roots.iter().any(|root| {
+                    !root.restore && path.starts_with(Path::new(ROOTS_DIR).join(&root.name))
+                })
+            };
+            let chosen: Vec<PathBuf> = walk_files(scratch.path())?
+                .into_iter()
+                .filter(|path| matcher.is_selected(path) && restorable(path))
+                .collect();
+            let stale: Vec<PathBuf> = match mirror {
+                true => self
+                    .files_to_delete(settings)?
+                    .into_iter()
+                    .filter(|path| matcher.is_selected(path))
+                    .collect(),
+                false => Vec::new(),
+            };
+            if chosen.is_empty() && stale.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no file in the snapshot matches the selection",
+                ));
+            }
+            for relative in &chosen {
+                restore::replace_file(&scratch.path().join(relative), &self.live_path(relative))?;
+            }
+            for relative in &stale {
+                fs::remove_file(self.live_path(relative))?;
+            }
+            Ok([chosen, stale].concat())
+        })();
+        match &result {
+            Ok(files) => println!(
+                "\x1b[32mSuccessfully restored \x1b[34m{}\x1b[0m ({} selected files) in \x1b[36m{:.2?}\x1b[0m",
+                self.parent_game,
+                files.len(),
+                start.elapsed()
+            ),
+            Err(err) => eprintln!("Failed to restore {} due to {}", self.parent_game, err),
+        }
+        result
+    }
+
+    /// Writes the snapshot's files into `dst`, overwriting what is there. Does no staging of its own.
+    pub fn restore_into(&self, dst: &Path, settings: &Settings) -> Result<String, io::Error> {
+        match self.storage {
+            StorageMode::Directory | StorageMode::Linked => {
+                copy_tree(&self.data_path(), dst).map(|report| report.to_string())
             }
-        } else {
-            // Handle the case where the parent directory cannot be determined
-            eprintln!(
-                "Failed to determine the parent directory of the production path: {:?}",
-                self.production_path
+            StorageMode::Dedup => {
+                let store = Store::open_with(settings)?;
+                let manifest = store.read_manifest(&self.manifest_path())?;
+                store.restore(&manifest, dst)?;
+                Ok(format!("{} files", manifest.files.len()))
+            }
+            StorageMode::Archive { .. } => archive::extract_archive(&self.archive_path(), dst)
+                .map(|_| "streamed from archive".to_string()),
+        }
+    }
+
+    /**
+    # Usecase
+    Dry run of a mirrored restore: the files in `production_path` that are not in this snapshot and would be deleted.
+    Files in additional roots are listed under `roots/<name>/`.
+    */
+    pub fn files_to_delete(&self, settings: &Settings) -> Result<Vec<PathBuf>, io::Error> {
+        let mut files = self.files_to_delete_part(settings)?;
+        for (root, part) in self.root_saves()? {
+            if root.restore {
+                let prefix = Path::new(ROOTS_DIR).join(&root.name);
+                files.extend(
+                    part.files_to_delete_part(settings)?
+                        .into_iter()
+                        .map(|path| prefix.join(path)),
+                );
+            }
+        }
+        Ok(files)
+    }
+
+    fn files_to_delete_part(&self, settings: &Settings) -> Result<Vec<PathBuf>, io::Error> {
+        if !self.production_path.exists() {
+            return Ok(Vec::new());
+        }
+        let snapshot: BTreeSet<PathBuf> = self.list_files_part(settings)?.into_iter().collect();
+        let (selected, _) = self.partition_live_files()?;
+        Ok(selected
+            .into_iter()
+            .filter(|path| !snapshot.contains(path))
+            .collect())
+    }
+
+    /**
+    # Usecase
+    Lists the files held by this snapshot, relative to `production_path`, without restoring anything.
+    Archives are read header by header, so nothing gets extracted to disk.
+    Files in additional roots are listed under `roots/<name>/`.
+    */
+    pub fn list_files(&self, settings: &Settings) -> Result<Vec<PathBuf>, io::Error> {
+        let mut files = self.list_files_part(settings)?;
+        for (root, part) in self.root_saves()? {
+            let prefix = Path::new(ROOTS_DIR).join(&root.name);
+            files.extend(
+                part.list_files_part(settings)?
+                    .into_iter()
+                    .map(|path| prefix.join(path)),
             );
         }
+        Ok(files)
+    }
+
+    fn list_files_part(&self, settings: &Settings) -> Result<Vec<PathBuf>, io::Error> {
+        match self.storage {
+            StorageMode::Directory | StorageMode::Linked => walk_files(&self.data_path()),
+            StorageMode::Dedup => Ok(Store::open_with(settings)?
+                .read_manifest(&self.manifest_path())?
+                .files
+                .into_keys()
+                .collect()),
+            StorageMode::Archive { .. } => Ok(archive::list_archive(&self.archive_path())?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect()),
+        }
+    }
+
+    /**
+    # Usecase
+    Removes the snapshot from disk. For dedup snapshots, the blobs are only deleted once no other snapshot references them.
+    Copies on the mirror destinations are left alone, callers dropping the snapshot for good also call `mirror::forget`.
+    */
+    pub fn delete(&self, settings: &Settings) -> Result<(), io::Error> {
+        if self.storage == StorageMode::Dedup {
+            let store = Store::open_with(settings)?;
+            let manifest = store.read_manifest(&self.manifest_path())?;
+            store.release(&manifest)?;
+            for (_, part) in self.root_saves()? {
+                store.release(&store.read_manifest(&part.manifest_path())?)?;
+            }
+        }
+        match fs::remove_dir_all(&self.backup_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
     }
 }
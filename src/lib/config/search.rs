@@ -0,0 +1,52 @@
+use crate::config::{game::Game, save::Save};
+
+/**
+# Usecase
+Finds snapshots by what the user wrote on them. Every condition that is set has to hold;
+an empty query matches everything.
+- `text`: case-insensitive substring of the label, the notes or any tag.
+- `tags`: the snapshot carries every one of these tags (case-insensitive).
+- `pinned`: only pinned (`Some(true)`) or only unpinned (`Some(false)`) snapshots.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SaveQuery {
+    pub text: Option<String>,
+    pub tags: Vec<String>,
+    pub pinned: Option<bool>,
+}
+
+impl SaveQuery {
+    pub fn matches(&self, save: &Save) -> bool {
+        if self.pinned.is_some_and(|pinned| pinned != save.pinned) {
+            return false;
+        }
+        let has_tag =
+            |wanted: &String| save.tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted));
+        if !self.tags.iter().all(has_tag) {
+            return false;
+        }
+        match &self.text {
+            Some(text) => {
+                let needle = text.to_lowercase();
+                save.label
+                    .iter()
+                    .chain(save.notes.iter())
+                    .chain(save.tags.iter())
+                    .any(|field| field.to_lowercase().contains(&needle))
+            }
+            None => true,
+        }
+    }
+}
+
+/// Every snapshot in the catalog that matches `query`, with the game it belongs to.
+pub fn search<'a>(games: &'a [Game], query: &SaveQuery) -> Vec<(&'a Game, &'a Save)> {
+    games
+        .iter()
+        .flat_map(|game| {
+            game.find_saves(query)
+                .into_iter()
+                .map(move |save| (game, save))
+        })
+        .collect()
+}
@@ -0,0 +1,189 @@
+use crate::config::{
+    crypto, fsck::QUARANTINE_DIR, game::Game, save::Save, slots::SLOTS_DIR, store::Store,
+    write_atomic,
+};
+use crate::settings::{Settings, StorageMode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// File inside `backup_path` describing the snapshot, so the store can be read without conf.json.
+pub const SIDECAR_NAME: &str = "snapshot.json";
+
+/**
+# Usecase
+Everything the catalog knows about one snapshot, kept next to it: the game ID and title, when it was taken,
+the production path and extra roots it came from, storage mode, rules, label, notes and tags.
+`manifest_hash` is the BLAKE3 hash of `manifest.json` as stored, to tell whether the two still belong together.
+For dedup snapshots in an encrypted store the sidecar is sealed like the manifest.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sidecar {
+    #[serde(default)]
+    pub manifest_hash: Option<String>,
+    pub save: Save,
+}
+
+/// BLAKE3 of the snapshot's manifest file, or `None` if it has none.
+pub fn manifest_hash(save: &Save) -> Result<Option<String>, io::Error> {
+    match fs::read(save.manifest_path()) {
+        Ok(data) => Ok(Some(blake3::hash(&data).to_hex().to_string())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes or refreshes the sidecar of `save`, e.g. after its label or tags changed. Replaces it atomically.
+pub fn write(save: &Save, settings: &Settings) -> Result<(), io::Error> {
+    let sidecar = Sidecar {
+        manifest_hash: manifest_hash(save)?,
+        save: save.clone(),
+    };
+    let json = serde_json::to_vec_pretty(&sidecar).map_err(io::Error::other)?;
+    let path = save.backup_path.join(SIDECAR_NAME);
+    // NOTE: Only dedup snapshots can be encrypted, everything else is plain on disk anyway
+    match (save.storage, &settings.encryption) {
+        (StorageMode::Dedup, Some(_)) => {
+            Store::open_with(settings)?.write_sealed(&json, &path, b"sidecar")
+        }
+        _ => write_atomic(&path, &json),
+    }
+}
+
+/// Reads the sidecar in `dir`. `store` is opened on first use, so plain stores are never touched.
+pub fn read(
+    dir: &Path,
+    store: &mut Option<Store>,
+    settings: &Settings,
+) -> Result<Sidecar, io::Error> {
+    let path = dir.join(SIDECAR_NAME);
+    let data = fs::read(&path)?;
+    let sealed = crypto::is_encrypted(&data);
+    let json = match sealed {
+        true => {
+            if store.is_none() {
+                *store = Some(Store::open_with(settings)?);
+            }
+            store
+                .as_ref()
+                .ok_or(io::ErrorKind::PermissionDenied)?
+                .read_sealed(&path, b"sidecar")?
+        }
+        false => data,
+    };
+    let sidecar: Sidecar = serde_json::from_slice(&json).map_err(io::Error::other)?;
+    // NOTE: Dedup sidecars are sealed once the store has a key, a plain one was swapped in or predates it
+    if !sealed && sidecar.save.storage == StorageMode::Dedup && settings.encryption.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} is not sealed although the store is encrypted", path),
+        ));
+    }
+    Ok(sidecar)
+}
+
+/**
+# Usecase
+Whether a directory called `name` under `save_base_path` is one of ours rather than a game or snapshot: the blob
+store, slots, quarantine, or a staging sibling left behind by `restore::sibling_path`.
+Other names starting with a dot are kept, game titles such as ".hack//G.U." end up as one.
+*/
+pub fn is_reserved_dir(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    [".store", SLOTS_DIR, QUARANTINE_DIR].contains(&name.as_ref())
+        || (name.starts_with('.') && name.contains(".oxi-"))
+}
+
+/**
+# Usecase
+Every directory under `base` that holds a sidecar. Snapshots are not searched further, and neither is anything
+`is_reserved_dir`, so leftover staging directories are skipped too.
+*/
+pub fn find_snapshots(base: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut found = Vec::new();
+    let mut pending = vec![base.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if dir.join(SIDECAR_NAME).is_file() {
+            found.push(dir);
+            continue;
+        }
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && !is_reserved_dir(&entry.file_name()) {
+                pending.push(entry.path());
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/**
+# Usecase
+Rebuilds the catalog from the sidecars under `save_base_path`, for when conf.json is lost or corrupted.
+Snapshots are grouped into games by game ID, or by title for games without one, and each game takes the title
+of its newest snapshot. `backup_path` is set to where the snapshot was found, so a store that was moved as a
+whole is picked up in its new place. A snapshot whose manifest no longer matches its sidecar is still listed,
+with a warning, so `verify` can look at it.
+Restore history, drill results, slots and per-game overrides other than the extra roots are not part of any
+snapshot and start out empty.
+*/
+pub fn rebuild_catalog(settings: &Settings) -> Result<Vec<Game>, io::Error> {
+    let mut store = None;
+    let mut by_game: BTreeMap<(u32, String), Vec<Save>> = BTreeMap::new();
+    for dir in find_snapshots(&settings.save_base_path)? {
+        let sidecar = match read(&dir, &mut store, settings) {
+            Ok(sidecar) => sidecar,
+            Err(err) => {
+                eprintln!("Skipping {:?}, its sidecar could not be read: {}", dir, err);
+                continue;
+            }
+        };
+        let mut save = sidecar.save;
+        save.backup_path = dir;
+        if manifest_hash(&save)? != sidecar.manifest_hash {
+            eprintln!(
+                "\x1b[33mThe manifest of {:?} does not match its sidecar\x1b[0m",
+                save.backup_path
+            );
+        }
+        let key = match save.game_id {
+            0 => (0, save.parent_game.clone()),
+            game_id => (game_id, String::new()),
+        };
+        by_game.entry(key).or_default().push(save);
+    }
+
+    let mut games: Vec<Game> = by_game.into_values().filter_map(game_from_saves).collect();
+    games.sort_by(|a, b| a.game_title.cmp(&b.game_title));
+    Ok(games)
+}
+
+/// A catalog entry for a game known only from its snapshots, named after the newest one.
+pub(crate) fn game_from_saves(mut saves: Vec<Save>) -> Option<Game> {
+    saves.sort_by_key(|save| save.id);
+    let newest = saves.last()?.clone();
+    Some(Game {
+        game_title: newest.parent_game,
+        game_id: newest.game_id,
+        install_path: None,
+        save_path: Some(newest.production_path),
+        publisher: None,
+        developer: None,
+        saves: Some(saves),
+        thumbnail: Vec::new(),
+        storage: None,
+        restores: Vec::new(),
+        delete_on_restore: None,
+        last_drill: None,
+        rules: None,
+        // NOTE: Keep capturing the same extra locations in new snapshots
+        roots: newest.roots,
+        retention: None,
+        slots: Vec::new(),
+    })
+}
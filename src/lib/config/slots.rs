@@ -0,0 +1,97 @@
+use crate::config::{
+    check_plain_name, copy::copy_files, restore, rules::Rules, timestamps, walk_files,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Directory inside a game's backup directory that holds its quick-swap slots.
+pub const SLOTS_DIR: &str = ".slots";
+
+/**
+# Usecase
+A named practice state, e.g. "margit" or "pre-skip". Unlike a `Save`, a slot is overwritten in place every
+time it is saved into and only ever holds a plain copy, so saving and loading cost no more than copying the files.
+There is no manifest, history or pre-restore safety snapshot; the numbered snapshots are still there for that.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Slot {
+    pub name: String,
+    pub production_path: PathBuf,
+    #[serde(with = "timestamps")]
+    pub saved_at: DateTime<Utc>,
+    /// Where the slot's copy of the save lives.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub rules: Option<Rules>,
+}
+
+impl Slot {
+    /// Files below `production_path` the slot's rules select, and those they leave alone.
+    fn partition_live_files(&self) -> Result<(Vec<PathBuf>, Vec<PathBuf>), io::Error> {
+        let files = match self.production_path.exists() {
+            true => walk_files(&self.production_path)?,
+            false => Vec::new(),
+        };
+        match &self.rules {
+            Some(rules) => Ok(rules.compile()?.partition(files)),
+            None => Ok((files, Vec::new())),
+        }
+    }
+
+    /// Overwrites the slot with the live save. The old contents stay in place until the new copy is complete.
+    pub fn save(&self) -> Result<usize, io::Error> {
+        check_plain_name(&self.name)?;
+        let (selected, _) = self.partition_live_files()?;
+        let parent = self.path.parent().ok_or(io::ErrorKind::InvalidInput)?;
+        fs::create_dir_all(parent)?;
+        let staging = restore::sibling_path(&self.path, "staging")?;
+        replace_dir(&staging, &self.path, |staging| {
+            copy_files(&self.production_path, &selected, staging).map(|_| ())
+        })?;
+        Ok(selected.len())
+    }
+
+    /**
+    # Usecase
+    Makes the live save match the slot: selected files that are not in the slot are removed, files the rules
+    leave alone are kept. The new save is swapped in as a whole like a normal restore, but not synced to disk first,
+    trading durability on power loss for latency. Symlinks and empty directories in the live save are kept as well.
+    */
+    pub fn load(&self) -> Result<usize, io::Error> {
+        let files = walk_files(&self.path)?;
+        let (_, excluded) = self.partition_live_files()?;
+        let target = restore::swap_target(&self.production_path)?;
+        let staging = restore::sibling_path(&target, "staging")?;
+        replace_dir(&staging, &target, |staging| {
+            copy_files(&self.production_path, &excluded, staging)?;
+            copy_files(&self.path, &files, staging)?;
+            if target.exists() {
+                restore::carry_over_links(&target, staging)?;
+            }
+            Ok(())
+        })?;
+        Ok(files.len())
+    }
+}
+
+/// Builds a fresh `staging` directory with `fill` and swaps it in for `target`, cleaning up on failure.
+fn replace_dir(
+    staging: &Path,
+    target: &Path,
+    fill: impl FnOnce(&Path) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    if staging.exists() {
+        fs::remove_dir_all(staging)?;
+    }
+    let result = fs::create_dir_all(staging)
+        .and_then(|_| fill(staging))
+        .and_then(|_| restore::swap_in(staging, target));
+    if result.is_err() && staging.exists() {
+        let _ = fs::remove_dir_all(staging);
+    }
+    result
+}
@@ -185,6 +185,14 @@ pub fn return_steamgames(library: &Path, thumb_path: &Path) -> Option<Vec<Game>>
                                     publisher: None,
                                     save_path: None,
                                     saves: None,
+                                    storage: None,
+                                    restores: Vec::new(),
+                                    delete_on_restore: None,
+                                    last_drill: None,
+                                    rules: None,
+                                    roots: Vec::new(),
+                                    retention: None,
+                                    slots: Vec::new(),
                                 };
                                 steamgames.push(game);
                             }
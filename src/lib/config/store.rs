@@ -0,0 +1,442 @@
+use crate::config::copy::copy_file;
+use crate::config::crypto::{self, Cipher, Keyring};
+use crate::config::manifest::{from_secs, modified_secs, Manifest, ManifestEntry};
+use crate::config::{walk_files, write_atomic};
+use crate::settings::{KeySource, Settings};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+
+/// Rejects anything but the 64 hex characters of a blake3 hash, so a name read from a manifest can only ever
+/// point at a blob inside the store.
+pub fn check_hash(hash: &str) -> io::Result<()> {
+    match hash.len() == 64 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} is not a blob hash", hash),
+        )),
+    }
+}
+
+fn plain_in_encrypted_store() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "unencrypted data in an encrypted store, it was either tampered with or predates encryption",
+    )
+}
+
+/// Feeds everything read through it into `hasher` as well.
+struct HashingReader<R> {
+    inner: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/**
+# Usecase
+A content-addressed blob store shared by every game under `save_base_path`.
+
+Each file is stored once under `blobs/<first two hex chars>/<hash>`, no matter how many
+snapshots (or games) contain it. `refs.json` keeps a reference count per blob so that a
+snapshot can be released without touching blobs that other snapshots still point at.
+
+When unlocked with a key, blobs and manifests are encrypted and authenticated, and blob
+names become keyed hashes so they give nothing away about the files' contents.
+*/
+pub struct Store {
+    root: PathBuf,
+    cipher: Option<Cipher>,
+}
+
+impl Store {
+    /// Opens (and if needed creates) the store at `root`.
+    pub fn open(root: &Path) -> io::Result<Store> {
+        fs::create_dir_all(root.join("blobs"))?;
+        fs::create_dir_all(root.join("tmp"))?;
+        Ok(Store {
+            root: root.to_path_buf(),
+            cipher: None,
+        })
+    }
+
+    /// Opens the store configured in `settings`, unlocking it if encryption is configured.
+    /// Refuses to open an encrypted store without a key, so plain blobs never end up in it.
+    pub fn open_with(settings: &Settings) -> io::Result<Store> {
+        let mut store = Store::open(&settings.store_path())?;
+        match &settings.encryption {
+            Some(source) => store.unlock(source)?,
+            None if store.keyring_path().exists() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "the store is encrypted but no key is configured",
+                ))
+            }
+            None => {}
+        }
+        Ok(store)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn keyring_path(&self) -> PathBuf {
+        self.root.join("keyring.json")
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Unwraps the data key with `source`, creating a new keyring if the store has none yet.
+    pub fn unlock(&mut self, source: &KeySource) -> io::Result<()> {
+        let cipher = if self.keyring_path().exists() {
+            Keyring::load(&self.keyring_path())?.unwrap(source)?
+        } else {
+            let cipher = Cipher::generate();
+            Keyring::wrap(source, &cipher)?.save(&self.keyring_path())?;
+            cipher
+        };
+        self.cipher = Some(cipher);
+        Ok(())
+    }
+
+    /// Re-wraps the data key with `new`. Blobs and manifests are left untouched.
+    pub fn rekey(&mut self, old: &KeySource, new: &KeySource) -> io::Result<()> {
+        let cipher = Keyring::load(&self.keyring_path())?.unwrap(old)?;
+        let mut tmp = NamedTempFile::new_in(self.root.join("tmp"))?;
+        let keyring = Keyring::wrap(new, &cipher)?;
+        tmp.write_all(&serde_json::to_vec_pretty(&keyring).map_err(io::Error::other)?)?;
+        tmp.persist(self.keyring_path()).map_err(|err| err.error)?;
+        self.cipher = Some(cipher);
+        Ok(())
+    }
+
+    /// Where the blob `hash` is kept. Hashes from untrusted places have to pass `check_hash` first.
+    pub fn blob_path(&self, hash: &str) -> PathBuf {
+        let prefix = hash.get(..2).unwrap_or_default();
+        self.root.join("blobs").join(prefix).join(hash)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.blob_path(hash).exists()
+    }
+
+    fn hasher(&self) -> blake3::Hasher {
+        match &self.cipher {
+            Some(cipher) => blake3::Hasher::new_keyed(&cipher.id_key()),
+            None => blake3::Hasher::new(),
+        }
+    }
+
+    fn hash_file(&self, src: &Path) -> io::Result<ManifestEntry> {
+        let file = File::open(src)?;
+        let modified = modified_secs(&file.metadata()?);
+        let mut hasher = self.hasher();
+        let size = io::copy(&mut BufReader::new(file), &mut hasher)?;
+        Ok(ManifestEntry {
+            hash: hasher.finalize().to_hex().to_string(),
+            size,
+            modified,
+        })
+    }
+
+    /// Hashes `src` and moves it into the store unless a blob with the same hash already exists.
+    pub fn put_file(&self, src: &Path) -> io::Result<ManifestEntry> {
+        let mut tmp = NamedTempFile::new_in(self.root.join("tmp"))?;
+        let entry = match &self.cipher {
+            // NOTE: Encrypted blobs are bound to their hash, so it has to be known before encrypting
+            Some(cipher) => {
+                let entry = self.hash_file(src)?;
+                if self.contains(&entry.hash) {
+                    return Ok(entry);
+                }
+                // NOTE: The file is read a second time to encrypt it, so what was encrypted is hashed again.
+                // A game writing in between would otherwise leave a blob whose name promises other data
+                let mut reader = HashingReader {
+                    inner: BufReader::new(File::open(src)?),
+                    hasher: self.hasher(),
+                };
+                cipher.encrypt_stream(&mut reader, &mut tmp, entry.hash.as_bytes())?;
+                if reader.hasher.finalize().to_hex().as_str() != entry.hash {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Interrupted,
+                        format!("{:?} changed while it was being stored", src),
+                    ));
+                }
+                entry
+            }
+            None => {
+                let mut reader = File::open(src)?;
+                let modified = modified_secs(&reader.metadata()?);
+                let mut hasher = self.hasher();
+                let mut buffer = [0u8; 64 * 1024];
+                let mut size = 0;
+                loop {
+                    let read = reader.read(&mut buffer)?;
+                    if read == 0 {
+                        break;
+                    }
+                    hasher.update(&buffer[..read]);
+                    tmp.write_all(&buffer[..read])?;
+                    size += read as u64;
+                }
+                ManifestEntry {
+                    hash: hasher.finalize().to_hex().to_string(),
+                    size,
+                    modified,
+                }
+            }
+        };
+        let blob = self.blob_path(&entry.hash);
+        if !blob.exists() {
+            if let Some(parent) = blob.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            tmp.persist(&blob).map_err(|err| err.error)?;
+        }
+        Ok(entry)
+    }
+
+    /// Streams the plaintext of a blob into `writer`, authenticating it if the store is encrypted.
+    pub fn read_blob<W: Write>(&self, hash: &str, writer: W) -> io::Result<()> {
+        check_hash(hash)?;
+        let reader = BufReader::new(File::open(self.blob_path(hash))?);
+        self.decode_blob(hash, reader, writer)
+    }
+
+    /// Like `read_blob`, for the stored form of blob `hash` read from `reader` instead of from the store.
+    fn decode_blob<R: Read, W: Write>(
+        &self,
+        hash: &str,
+        mut reader: R,
+        writer: W,
+    ) -> io::Result<()> {
+        let mut magic = [0u8; 8];
+        let peeked = reader.read(&mut magic)?;
+        let mut reader = (&magic[..peeked]).chain(reader);
+        match (&self.cipher, crypto::is_encrypted(&magic[..peeked])) {
+            (Some(cipher), true) => cipher.decrypt_stream(reader, writer, hash.as_bytes()),
+            (None, true) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "blob is encrypted but the store is locked",
+            )),
+            (Some(_), false) => Err(plain_in_encrypted_store()),
+            (None, false) => io::copy(&mut reader, &mut { writer }).map(|_| ()),
+        }
+    }
+
+    /**
+    # Usecase
+    Reads a blob back and recomputes its hash. Returns `Ok(false)` if the contents do not match
+    the name, and an `InvalidData` error if an encrypted blob fails authentication.
+    */
+    pub fn check_blob(&self, hash: &str) -> io::Result<bool> {
+        let mut hasher = self.hasher();
+        self.read_blob(hash, &mut hasher)?;
+        Ok(hasher.finalize().to_hex().as_str() == hash)
+    }
+
+    /// Like `check_blob`, for `data` that is meant to become blob `hash`, e.g. one fetched from a backend.
+    pub fn check_blob_data(&self, hash: &str, data: &[u8]) -> io::Result<bool> {
+        check_hash(hash)?;
+        let mut hasher = self.hasher();
+        self.decode_blob(hash, data, &mut hasher)?;
+        Ok(hasher.finalize().to_hex().as_str() == hash)
+    }
+
+    /// Hashes every file under `dir` the way this store names blobs, without storing anything.
+    pub fn manifest_of(&self, dir: &Path) -> io::Result<Manifest> {
+        self.manifest_of_files(dir, &walk_files(dir)?)
+    }
+
+    /// Like `manifest_of`, for just the given `files` (relative to `dir`).
+    pub fn manifest_of_files(&self, dir: &Path, files: &[PathBuf]) -> io::Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for relative in files {
+            let entry = self.hash_file(&dir.join(relative))?;
+            manifest.files.insert(relative.clone(), entry);
+        }
+        Ok(manifest)
+    }
+
+    /// Stores the given `files` (relative to `src`) and returns the manifest describing them.
+    /// Each referenced blob has its reference count bumped once per entry.
+    pub fn snapshot(&self, src: &Path, files: &[PathBuf]) -> io::Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for relative in files {
+            let entry = self.put_file(&src.join(relative))?;
+            manifest.files.insert(relative.clone(), entry);
+        }
+        self.retain(&manifest)?;
+        Ok(manifest)
+    }
+
+    /// Bumps the reference count of every blob in `manifest` once per entry, the counterpart of `release`.
+    pub fn retain(&self, manifest: &Manifest) -> io::Result<()> {
+        let mut refs = self.read_refs()?;
+        manifest.files.values().for_each(|entry| {
+            *refs.entry(entry.hash.clone()).or_insert(0) += 1;
+        });
+        self.write_refs(&refs)
+    }
+
+    /// Writes every file in `manifest` below `dst`, overwriting files that already exist.
+    /// Each file gets back the modification time recorded for it, not that of the blob it came from.
+    pub fn restore(&self, manifest: &Manifest, dst: &Path) -> io::Result<()> {
+        for (relative, entry) in &manifest.files {
+            let target = dst.join(relative);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            match self.cipher {
+                // NOTE: Plain blobs can be reflinked straight out of the store
+                None => copy_file(&self.blob_path(&entry.hash), &target).map(|_| ())?,
+                Some(_) => self.read_blob(&entry.hash, File::create(&target)?)?,
+            }
+            if let Some(modified) = entry.modified {
+                File::options()
+                    .write(true)
+                    .open(&target)?
+                    .set_modified(from_secs(modified))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `manifest` to `path`, sealed with the store's key if it has one.
+    pub fn write_manifest(&self, manifest: &Manifest, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(manifest).map_err(io::Error::other)?;
+        self.write_sealed(&json, path, b"manifest")
+    }
+
+    pub fn read_manifest(&self, path: &Path) -> io::Result<Manifest> {
+        let json = self.read_sealed(path, b"manifest")?;
+        let manifest: Manifest = serde_json::from_slice(&json).map_err(io::Error::other)?;
+        for entry in manifest.files.values() {
+            check_hash(&entry.hash)?;
+        }
+        Ok(manifest)
+    }
+
+    /**
+    # Usecase
+    Brings a snapshot taken before the store was encrypted under its key: each blob of the plain manifest at
+    `path` is checked against its name, stored again encrypted, and the manifest is sealed in place.
+    Once a store has a key, plain data is refused as possibly tampered with, so running this is the explicit way
+    to keep such snapshots readable. Returns `false` if the manifest is already sealed.
+    */
+    pub fn encrypt_legacy_manifest(&self, path: &Path) -> io::Result<bool> {
+        if self.cipher.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the store has no key to encrypt with",
+            ));
+        }
+        let data = fs::read(path)?;
+        if crypto::is_encrypted(&data) {
+            return Ok(false);
+        }
+        let legacy: Manifest = serde_json::from_slice(&data).map_err(io::Error::other)?;
+        let mut sealed = Manifest::default();
+        for (relative, entry) in &legacy.files {
+            check_hash(&entry.hash)?;
+            let blob = self.blob_path(&entry.hash);
+            let mut hasher = blake3::Hasher::new();
+            io::copy(&mut BufReader::new(File::open(&blob)?), &mut hasher)?;
+            if hasher.finalize().to_hex().as_str() != entry.hash {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("blob {} does not match its contents", entry.hash),
+                ));
+            }
+            let stored = self.put_file(&blob)?;
+            // NOTE: The blob's own mtime is that of whichever file first produced it
+            let modified = entry.modified;
+            sealed
+                .files
+                .insert(relative.clone(), ManifestEntry { modified, ..stored });
+        }
+        self.retain(&sealed)?;
+        self.write_manifest(&sealed, path)?;
+        self.release(&legacy)?;
+        Ok(true)
+    }
+
+    /// Atomically writes small metadata such as a manifest to `path`, sealed with the store's key if it has one.
+    /// `kind` is bound into the ciphertext, so one kind of file cannot be swapped in for another.
+    pub fn write_sealed(&self, data: &[u8], path: &Path, kind: &[u8]) -> io::Result<()> {
+        match &self.cipher {
+            Some(cipher) => write_atomic(path, &cipher.seal(data, kind)?),
+            None => write_atomic(path, data),
+        }
+    }
+
+    pub fn read_sealed(&self, path: &Path, kind: &[u8]) -> io::Result<Vec<u8>> {
+        let data = fs::read(path)?;
+        match (&self.cipher, crypto::is_encrypted(&data)) {
+            (Some(cipher), true) => cipher.open(&data, kind),
+            (None, true) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "file is encrypted but the store is locked",
+            )),
+            (Some(_), false) => Err(plain_in_encrypted_store()),
+            (None, false) => Ok(data),
+        }
+    }
+
+    /// Drops the references held by `manifest` and deletes blobs nobody points at anymore.
+    /// Returns the number of blobs removed from disk.
+    pub fn release(&self, manifest: &Manifest) -> io::Result<usize> {
+        let mut refs = self.read_refs()?;
+        let mut removed = 0;
+        for entry in manifest.files.values() {
+            if let Some(count) = refs.get_mut(&entry.hash) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    refs.remove(&entry.hash);
+                    match fs::remove_file(self.blob_path(&entry.hash)) {
+                        Ok(()) => removed += 1,
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
+        self.write_refs(&refs)?;
+        Ok(removed)
+    }
+
+    pub fn ref_count(&self, hash: &str) -> io::Result<u64> {
+        Ok(self.read_refs()?.get(hash).copied().unwrap_or(0))
+    }
+
+    fn read_refs(&self) -> io::Result<BTreeMap<String, u64>> {
+        match File::open(self.root.join("refs.json")) {
+            Ok(file) => serde_json::from_reader(file).map_err(io::Error::other),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // NOTE: Written to a temp file first so a crash never leaves a truncated refs.json behind
+    fn write_refs(&self, refs: &BTreeMap<String, u64>) -> io::Result<()> {
+        let mut tmp = NamedTempFile::new_in(self.root.join("tmp"))?;
+        serde_json::to_writer(&mut tmp, refs).map_err(io::Error::other)?;
+        tmp.persist(self.root.join("refs.json"))
+            .map_err(|err| err.error)?;
+        Ok(())
+    }
+}
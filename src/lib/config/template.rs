@@ -0,0 +1,147 @@
+use chrono::{DateTime, Utc};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use ulid::Ulid;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// The layout snapshots have always had: `<save_base_path>/<title>/<snapshot ID>`.
+pub const DEFAULT_TEMPLATE: &str = "{store}/{title}/{snapshot}";
+
+/// Longest rendered path segment in bytes, leaving room for suffixes under the usual 255 byte limit.
+const MAX_SEGMENT_BYTES: usize = 200;
+
+/// Names Windows refuses for files and directories, whatever the extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/**
+# Usecase
+Turns arbitrary text, usually a game title, into a single path segment that is valid on Linux, macOS and Windows.
+The text is NFC normalized so titles typed or scraped with different Unicode forms end up in the same directory.
+Separators and characters Windows rejects become `_`, e.g. "Fate/Stay Night" becomes "Fate_Stay Night".
+*/
+pub fn sanitize_segment(text: &str) -> String {
+    let mut segment: String = text
+        .nfc()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // NOTE: Windows drops trailing dots and spaces, which would merge "Game." with "Game"
+    let trimmed = segment.trim().trim_end_matches('.').len();
+    segment.truncate(trimmed);
+    segment = segment.trim_start().to_string();
+    if segment.len() > MAX_SEGMENT_BYTES {
+        let end = (0..=MAX_SEGMENT_BYTES)
+            .rev()
+            .find(|end| segment.is_char_boundary(*end))
+            .unwrap_or(0);
+        segment.truncate(end);
+    }
+    let stem = segment.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(stem))
+    {
+        segment.push('_');
+    }
+    match segment.as_str() {
+        "" | "." | ".." => "_".to_string(),
+        _ => segment,
+    }
+}
+
+/// A lowercase, dash separated form of `text` with accents stripped, e.g. "Pokémon: Légendes" becomes "pokemon-legendes".
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.nfkd().filter(|c| !is_combining_mark(*c)) {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    sanitize_segment(slug)
+}
+
+/// What a path template can refer to for one snapshot.
+#[derive(Debug, Clone)]
+pub struct TemplateValues<'a> {
+    pub store: &'a Path,
+    pub title: &'a str,
+    pub game_id: u32,
+    pub snapshot: Ulid,
+    pub count: u16,
+    pub saved_at: DateTime<Utc>,
+}
+
+impl TemplateValues<'_> {
+    fn lookup(&self, name: &str) -> Option<String> {
+        match name {
+            "title" => Some(self.title.to_string()),
+            "title_slug" => Some(slugify(self.title)),
+            "id" | "game_id" => Some(self.game_id.to_string()),
+            "snapshot" => Some(self.snapshot.to_string()),
+            "count" => Some(self.count.to_string()),
+            // NOTE: No colons, they are not allowed in Windows paths
+            "timestamp" => Some(self.saved_at.format("%Y-%m-%dT%H-%M-%SZ").to_string()),
+            _ => None,
+        }
+    }
+}
+
+/**
+# Usecase
+Renders `Settings.path_template` into a snapshot's `backup_path`.
+The template is a `/` separated path whose segments may contain placeholders:
+- `{store}`: `save_base_path`. Only allowed as the whole first segment; relative templates start there anyway.
+- `{title}`: the game title, `{title_slug}`: its `slugify`d form.
+- `{id}` or `{game_id}`: the Steam app ID, which survives renaming the game.
+- `{snapshot}`: the snapshot's ULID, `{count}`: its display number.
+- `{timestamp}`: when it was taken, in UTC.
+
+Every rendered segment goes through `sanitize_segment`, so no value can add path components or escape the store.
+At least one of `{snapshot}`, `{timestamp}` or `{count}` has to appear so each snapshot gets its own directory.
+*/
+pub fn render(template: &str, values: &TemplateValues) -> Result<PathBuf, io::Error> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidInput, reason);
+    if !["{snapshot}", "{timestamp}", "{count}"]
+        .iter()
+        .any(|placeholder| template.contains(placeholder))
+    {
+        return Err(invalid(format!(
+            "path template {:?} does not tell snapshots apart",
+            template
+        )));
+    }
+    let mut path = values.store.to_path_buf();
+    for (index, segment) in template.split('/').enumerate() {
+        if segment.is_empty() || (index == 0 && segment == "{store}") {
+            continue;
+        }
+        let mut rendered = String::new();
+        let mut rest = segment;
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| invalid(format!("unclosed placeholder in {:?}", template)))?;
+            let name = &rest[start + 1..start + end];
+            let value = values
+                .lookup(name)
+                .ok_or_else(|| invalid(format!("unknown placeholder {{{}}}", name)))?;
+            rendered.push_str(&value);
+            rest = &rest[start + end + 1..];
+        }
+        rendered.push_str(rest);
+        path.push(sanitize_segment(&rendered));
+    }
+    Ok(path)
+}
@@ -0,0 +1,62 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+/**
+# Usecase
+`#[serde(with = "timestamps")]` for `DateTime<Utc>` fields in conf.json.
+Instants are written as RFC 3339 with an explicit `+00:00` offset, never a bare `Z`, so they cannot be mistaken
+for the legacy strings `legacy` still reads.
+*/
+pub fn serialize<S>(at: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&at.to_rfc3339_opts(SecondsFormat::AutoSi, false))
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    DateTime::parse_from_rfc3339(&text)
+        .map(|at| at.with_timezone(&Utc))
+        .map_err(D::Error::custom)
+}
+
+/**
+# Usecase
+`#[serde(with = "timestamps::legacy")]` for `Save.saved_at`, the only timestamp catalogs held before instants were
+stored. Written like `timestamps`, but legacy strings are still accepted and reinterpreted as local time.
+*/
+pub mod legacy {
+    use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    pub use super::serialize;
+
+    /// How timestamps used to be written: local wall clock time followed by a `Z` that wrongly claimed UTC.
+    const LEGACY_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+    /// Reads a timestamp in the legacy format as the local time it really was.
+    pub fn parse_legacy(text: &str) -> Option<DateTime<Utc>> {
+        let naive = NaiveDateTime::parse_from_str(text, LEGACY_FORMAT).ok()?;
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|at| at.with_timezone(&Utc))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        match parse_legacy(&text) {
+            Some(at) => Ok(at),
+            None => DateTime::parse_from_rfc3339(&text)
+                .map(|at| at.with_timezone(&Utc))
+                .map_err(D::Error::custom),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::config::{game::Game, manifest::Manifest};
+use crate::settings::Settings;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use ulid::Ulid;
+
+/// What `verify` found wrong with a snapshot. All paths are relative to the production directory.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// In the manifest but gone from the snapshot.
+    pub missing: Vec<PathBuf>,
+    /// In the snapshot but not in the manifest.
+    pub extra: Vec<PathBuf>,
+    /// Present, but the size or hash no longer matches the manifest.
+    pub corrupted: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.corrupted.is_empty()
+    }
+
+    /// Adds the findings of `other` with their paths placed under `prefix`.
+    pub fn absorb(&mut self, other: VerifyReport, prefix: &Path) {
+        let prefixed = |paths: Vec<PathBuf>| paths.into_iter().map(|path| prefix.join(path));
+        self.missing.extend(prefixed(other.missing));
+        self.extra.extend(prefixed(other.extra));
+        self.corrupted.extend(prefixed(other.corrupted));
+    }
+
+    /// Compares what a snapshot should hold against what it actually holds.
+    pub fn compare(expected: &Manifest, actual: &Manifest) -> VerifyReport {
+        let mut report = VerifyReport::default();
+        for (path, entry) in &expected.files {
+            match actual.files.get(path) {
+                None => report.missing.push(path.clone()),
+                Some(found) if found.hash != entry.hash || found.size != entry.size => {
+                    report.corrupted.push(path.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        report.extra = actual
+            .files
+            .keys()
+            .filter(|path| !expected.files.contains_key(*path))
+            .cloned()
+            .collect();
+        report
+    }
+}
+
+/// Result of verifying one snapshot as part of a bigger run.
+#[derive(Debug)]
+pub struct SaveVerification {
+    pub game_title: String,
+    pub id: Ulid,
+    /// For display.
+    pub count: u16,
+    pub result: Result<VerifyReport, io::Error>,
+}
+
+impl SaveVerification {
+    pub fn is_ok(&self) -> bool {
+        matches!(&self.result, Ok(report) if report.is_ok())
+    }
+}
+
+/**
+# Usecase
+Verifies every snapshot of every game in the catalog.
+Snapshots that cannot be checked at all (for instance because they predate manifests) come back as errors.
+*/
+pub fn verify_all(games: &[Game], settings: &Settings) -> Vec<SaveVerification> {
+    games
+        .iter()
+        .flat_map(|game| game.verify(settings))
+        .collect()
+}
@@ -1,10 +1,193 @@
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{env, io, path::PathBuf};
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct Settings {
     pub save_base_path: PathBuf,
+    /// Uses `save_base_path` as given instead of relative to home, like a local destination.
+    /// Set when the store is relocated to an absolute path, e.g. a drive mounted elsewhere.
+    #[serde(default)]
+    pub save_base_absolute: bool,
     pub game_conf_path: PathBuf,
     pub color_scheme: String,
     pub delete_on_restore: bool,
+    #[serde(default)]
+    pub storage: StorageMode,
+    /// Encrypts the dedup store when set. Turning this on makes every new snapshot use `StorageMode::Dedup`.
+    #[serde(default)]
+    pub encryption: Option<KeySource>,
+    /// Run a restore drill on each game's newest snapshot when the last one is older than this many days.
+    #[serde(default)]
+    pub drill_interval_days: Option<u32>,
+    /// Which snapshots `prune` keeps, unless a game has its own policy. `None` keeps everything.
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+    /// IANA name such as "Europe/Berlin" that timestamps are shown in. The system timezone when unset.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Where new snapshots go, see `template::render`. `template::DEFAULT_TEMPLATE` when unset.
+    #[serde(default)]
+    pub path_template: Option<String>,
+    /// Secondary places each snapshot is mirrored to after it is written to `save_base_path`.
+    #[serde(default)]
+    pub destinations: Vec<Destination>,
+    /// Snapshots kept in fewer places than this, `save_base_path` included, are reported as under-replicated.
+    /// Every configured place when unset.
+    #[serde(default)]
+    pub min_copies: Option<usize>,
+}
+
+/// # Description:
+/// A mirror destination. `name` identifies it in each snapshot's sync state, so renaming one starts it over.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Destination {
+    pub name: String,
+    pub backend: BackendConfig,
+}
+
+/// # Description:
+/// Grandfather-father-son retention. A snapshot is kept if any rule wants it:
+/// - `keep_last`: the newest N snapshots.
+/// - `keep_daily`/`keep_weekly`/`keep_monthly`: the newest snapshot of each of the last N days/ISO weeks/months that have one.
+/// - pinned snapshots are always kept.
+///
+/// If none of the `keep_*` rules are set, every snapshot is kept. `max_bytes` then drops the oldest
+/// unpinned snapshots that are still kept until the game fits, but never the newest one.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    #[serde(default)]
+    pub keep_last: Option<u32>,
+    #[serde(default)]
+    pub keep_daily: Option<u32>,
+    #[serde(default)]
+    pub keep_weekly: Option<u32>,
+    #[serde(default)]
+    pub keep_monthly: Option<u32>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+/// # Description:
+/// How the contents of a `Save` are laid out under `save_base_path`.
+/// - `Directory`: a plain copy of the production directory (the original behaviour).
+/// - `Dedup`: files are hashed into the shared blob store in `<save_base_path>/.store`, and the snapshot is only a manifest.
+/// - `Archive`: a single `.tar.zst` per snapshot, compressed at the given zstd `level`.
+/// - `Linked`: a plain copy like `Directory`, but files unchanged since the previous snapshot are hardlinked to it.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageMode {
+    #[default]
+    Directory,
+    Dedup,
+    Archive {
+        #[serde(default = "default_compression_level")]
+        level: i32,
+    },
+    Linked,
+}
+
+fn default_compression_level() -> i32 {
+    zstd::DEFAULT_COMPRESSION_LEVEL
+}
+
+/// # Description:
+/// Where the key protecting an encrypted store comes from.
+/// - `PassphraseEnv`: the name of an environment variable holding the passphrase.
+/// - `Passphrase`: the passphrase itself. Only meant for callers that prompt for it, never for oxi.json.
+/// - `KeyFile`: a file with at least 32 bytes of random key material.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    PassphraseEnv(String),
+    Passphrase(String),
+    KeyFile(PathBuf),
+}
+
+/// # Description:
+/// Where a `StorageBackend` keeps its objects.
+/// - `Local`: a directory, e.g. on a second disk or a mounted share. Unlike the other paths in oxi.json it is used
+///   as given, not relative to home, since external drives and shares are mounted elsewhere.
+/// - `S3`: a bucket in an S3-compatible object store. The credentials are read from the environment variables
+///   named by `access_key_env` and `secret_key_env`, never from oxi.json.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendConfig {
+    Local {
+        path: PathBuf,
+    },
+    S3 {
+        endpoint: String,
+        bucket: String,
+        #[serde(default = "default_region")]
+        region: String,
+        #[serde(default)]
+        prefix: String,
+        #[serde(default = "default_access_key_env")]
+        access_key_env: String,
+        #[serde(default = "default_secret_key_env")]
+        secret_key_env: String,
+    },
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_access_key_env() -> String {
+    "AWS_ACCESS_KEY_ID".to_string()
+}
+
+fn default_secret_key_env() -> String {
+    "AWS_SECRET_ACCESS_KEY".to_string()
+}
+
+impl KeySource {
+    pub fn passphrase(&self) -> Result<String, io::Error> {
+        match self {
+            KeySource::Passphrase(passphrase) => Ok(passphrase.clone()),
+            KeySource::PassphraseEnv(var) => env::var(var).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("environment variable {} is not set", var),
+                )
+            }),
+            KeySource::KeyFile(_) => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+}
+
+impl Settings {
+    fn tz(&self) -> Option<Tz> {
+        // NOTE: An unknown name falls back to the system timezone instead of failing every display
+        self.timezone.as_deref().and_then(|name| name.parse().ok())
+    }
+
+    /// `at` as wall clock time in the configured timezone.
+    pub fn local_time(&self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self.tz() {
+            Some(tz) => at.with_timezone(&tz).naive_local(),
+            None => at.with_timezone(&Local).naive_local(),
+        }
+    }
+
+    /// `at` formatted for display in the configured timezone, e.g. `2024-03-30 16:00:00 CET`.
+    pub fn format_time(&self, at: DateTime<Utc>) -> String {
+        match self.tz() {
+            Some(tz) => at
+                .with_timezone(&tz)
+                .format("%Y-%m-%d %H:%M:%S %Z")
+                .to_string(),
+            None => at
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S %:z")
+                .to_string(),
+        }
+    }
+
+    /// Root of the shared blob store used by `StorageMode::Dedup`.
+    pub fn store_path(&self) -> PathBuf {
+        self.save_base_path.join(".store")
+    }
 }
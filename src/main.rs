@@ -1,7 +1,21 @@
 use oxi::config::create_config;
-use oxi::config::{game::Game, gen_home, steam::discover_games, verify_conf, write_conf};
+use oxi::config::{
+    drill::run_due_drills,
+    fsck::{self, Action},
+    game::Game,
+    gen_home, mirror,
+    relocate::{self, relocate, RelocateProgress},
+    retention, save_conf,
+    sidecar::rebuild_catalog,
+    steam::discover_games,
+    under_home,
+    verify::verify_all,
+    verify_conf, write_conf,
+};
 use oxi::settings::Settings;
+use std::io::Write;
 use std::path::PathBuf;
+use ulid::Ulid;
 
 /// .
 /// # Examples
@@ -21,7 +35,6 @@ use std::path::PathBuf;
 ///```
 /// This function will return an error if the search result is not found
 // TODO: Add searches by other metrics besides title, such as publisher or developer
-#[allow(dead_code)]
 fn search_games(games: &[Game], search: String) -> Result<&Game, &'static str> {
     match games
         .iter()
@@ -32,6 +45,31 @@ fn search_games(games: &[Game], search: String) -> Result<&Game, &'static str> {
     }
 }
 
+/// Like `search_games`, for commands that change the game.
+fn search_games_mut(games: &mut [Game], search: String) -> Result<&mut Game, &'static str> {
+    match games
+        .iter_mut()
+        .find(|game| game.game_title.to_lowercase().contains(&search))
+    {
+        Some(game) => Ok(game),
+        None => Err("Game not found"),
+    }
+}
+
+/// A snapshot of `game` given on the command line, by its ID or by the count shown next to it.
+fn parse_snapshot(game: &Game, arg: &str) -> Result<Ulid, &'static str> {
+    if let Ok(id) = Ulid::from_string(arg) {
+        return Ok(id);
+    }
+    let count: u16 = arg.parse().map_err(|_| "Not a snapshot ID or count")?;
+    game.saves
+        .iter()
+        .flatten()
+        .find(|save| save.count == count)
+        .map(|save| save.id)
+        .ok_or("Snapshot not found")
+}
+
 fn main() {
     // Get the user's home directory
     let home_dir = gen_home().expect("All OSes should have a home dir??");
@@ -40,23 +78,324 @@ fn main() {
     if !settings_file.exists() {
         create_config();
     }
-    let prog_settings: &mut Settings =
-        &mut verify_conf::<Vec<Settings>>(settings_file.to_path_buf())[0];
-    let expanded_saves = PathBuf::from(format!(
-        "{}/{}",
-        &home_dir.display(),
-        prog_settings.save_base_path.display()
-    ));
-    prog_settings.save_base_path = expanded_saves;
-    let game_conf_path = PathBuf::from(format!(
-        "{}/{}conf.json",
-        home_dir.to_string_lossy(),
-        prog_settings.game_conf_path.to_string_lossy()
-    ));
-    let games: Vec<Game> = verify_conf(game_conf_path.clone());
+    let mut settings_list: Vec<Settings> = verify_conf(settings_file.to_path_buf());
+    let prog_settings: &mut Settings = &mut settings_list[0];
+    let command = std::env::args().nth(1);
+    if !prog_settings.save_base_absolute {
+        prog_settings.save_base_path = under_home(&home_dir, &prog_settings.save_base_path);
+    }
+    let game_conf_path = under_home(&home_dir, &prog_settings.game_conf_path).join("conf.json");
+    // NOTE: Runs before conf.json is read, since the point is to replace a lost or corrupted one
+    if command.as_deref() == Some("rebuild-catalog") {
+        match rebuild_catalog(prog_settings).and_then(|games| {
+            save_conf(&games, &game_conf_path)?;
+            Ok(games.len())
+        }) {
+            Ok(count) => println!(
+                "\x1b[32mRebuilt the catalog with \x1b[34m{}\x1b[32m games\x1b[0m",
+                count
+            ),
+            Err(err) => eprintln!("Could not rebuild the catalog due to {}", err),
+        }
+        return;
+    }
+    let mut games: Vec<Game> = verify_conf(game_conf_path.clone());
+    // NOTE: Catalogs from before snapshot IDs are moved over once, then this is a no-op
+    let mut migrated = false;
+    for game in &mut games {
+        match game.migrate_ids() {
+            Ok(changed) => migrated |= changed,
+            Err(err) => {
+                // NOTE: Some directories may have been renamed already, and the game was updated to match
+                migrated = true;
+                eprintln!(
+                    "Could not migrate {} to snapshot IDs: {}",
+                    game.game_title, err
+                );
+            }
+        }
+        if let Err(err) = game.backfill_sidecars(prog_settings) {
+            eprintln!(
+                "Could not write sidecars for {} due to {}",
+                game.game_title, err
+            );
+        }
+    }
+    // NOTE: The snapshot directories were renamed, so a catalog still naming the old ones must not be left behind
+    if migrated {
+        if let Err(err) = save_conf(&games, &game_conf_path) {
+            eprintln!(
+                "\x1b[31mCould not write {:?} after moving snapshots to IDs: {}\x1b[0m",
+                game_conf_path, err
+            );
+            std::process::exit(1);
+        }
+    }
+
+    // NOTE: Only games whose last drill is older than `drill_interval_days` are drilled
+    for drill in run_due_drills(&mut games, prog_settings, chrono::Utc::now()) {
+        match &drill.result {
+            Ok(report) if report.is_ok() => println!(
+                "\x1b[32mRestore drill of \x1b[34m{}\x1b[32m snapshot #{} passed\x1b[0m",
+                drill.game_title, drill.count
+            ),
+            Ok(report) => eprintln!(
+                "\x1b[31mRestore drill of {} snapshot #{} failed: {} missing, {} extra, {} corrupted\x1b[0m",
+                drill.game_title,
+                drill.count,
+                report.missing.len(),
+                report.extra.len(),
+                report.corrupted.len()
+            ),
+            Err(err) => eprintln!(
+                "\x1b[31mRestore drill of {} snapshot #{} could not run: {}\x1b[0m",
+                drill.game_title, drill.count, err
+            ),
+        }
+    }
+
+    // NOTE: Picks up destinations that came back since earlier snapshots were taken, the rest wait for next time
+    if !prog_settings.destinations.is_empty() {
+        let report = mirror::catch_up(&mut games, prog_settings);
+        if report.synced > 0 {
+            println!(
+                "\x1b[32mCaught up \x1b[34m{}\x1b[32m snapshots on the mirrors\x1b[0m",
+                report.synced
+            );
+        }
+        if report.failed > 0 {
+            eprintln!("Could not mirror {} snapshots", report.failed);
+        }
+    }
+
+    if command.as_deref() == Some("mirrors") {
+        for health in mirror::health(&games, prog_settings) {
+            match &health.unavailable {
+                None => println!(
+                    "\x1b[34m{}\x1b[0m: \x1b[32m{} synced\x1b[0m, \x1b[33m{} pending\x1b[0m",
+                    health.name, health.synced, health.pending
+                ),
+                Some(reason) => println!(
+                    "\x1b[34m{}\x1b[0m: \x1b[31munavailable ({})\x1b[0m, {} synced, {} pending",
+                    health.name, reason, health.synced, health.pending
+                ),
+            }
+            if let Some((at, error)) = &health.last_error {
+                println!(
+                    "  last failure at {}: {}",
+                    prog_settings.format_time(*at),
+                    error
+                );
+            }
+        }
+        for shortfall in mirror::under_replicated(&games, prog_settings, None) {
+            println!(
+                "\x1b[33m{} {} is kept in only {} place(s)\x1b[0m",
+                shortfall.game_title, shortfall.id, shortfall.copies
+            );
+        }
+    }
+
+    // NOTE: Explicit, since it rewrites snapshots that the encrypted store would otherwise refuse as tampered
+    if command.as_deref() == Some("encrypt-legacy") {
+        for game in &games {
+            match game.encrypt_legacy(prog_settings) {
+                Ok(0) => {}
+                Ok(count) => println!(
+                    "\x1b[32mEncrypted \x1b[34m{}\x1b[32m snapshots of \x1b[34m{}\x1b[0m",
+                    count, game.game_title
+                ),
+                Err(err) => eprintln!(
+                    "Could not encrypt the snapshots of {} due to {}",
+                    game.game_title, err
+                ),
+            }
+        }
+    }
+
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let positional: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+
+    if command.as_deref() == Some("fsck") {
+        // NOTE: Without an action flag this only reports, resolving is always asked for explicitly
+        let action = match (flag("--adopt"), flag("--remove"), flag("--quarantine")) {
+            (true, _, _) => Some(Action::Adopt),
+            (_, true, _) => Some(Action::Remove),
+            (_, _, true) => Some(Action::Quarantine),
+            _ => None,
+        };
+        match fsck::check(&games, prog_settings) {
+            Ok(problems) if problems.is_empty() => {
+                println!("\x1b[32mThe catalog matches the disk\x1b[0m")
+            }
+            Ok(problems) => {
+                for problem in &problems {
+                    match action.filter(|action| problem.actions().contains(action)) {
+                        Some(action) => match fsck::resolve(
+                            &mut games,
+                            prog_settings,
+                            &game_conf_path,
+                            problem,
+                            action,
+                        ) {
+                            Ok(()) => println!("\x1b[32m{:?}\x1b[0m {}", action, problem),
+                            Err(err) => eprintln!("Could not resolve {} due to {}", problem, err),
+                        },
+                        None => println!("{} {:?}", problem, problem.actions()),
+                    }
+                }
+            }
+            Err(err) => eprintln!("Could not check the catalog due to {}", err),
+        }
+    }
+
+    if command.as_deref() == Some("verify") {
+        let results = verify_all(&games, prog_settings);
+        let failed = results.iter().filter(|result| !result.is_ok()).count();
+        for verification in results.iter().filter(|result| !result.is_ok()) {
+            match &verification.result {
+                Ok(report) => eprintln!(
+                    "\x1b[31m{} snapshot #{}: {} missing, {} extra, {} corrupted\x1b[0m",
+                    verification.game_title,
+                    verification.count,
+                    report.missing.len(),
+                    report.extra.len(),
+                    report.corrupted.len()
+                ),
+                Err(err) => eprintln!(
+                    "\x1b[31m{} snapshot #{} could not be verified: {}\x1b[0m",
+                    verification.game_title, verification.count, err
+                ),
+            }
+        }
+        println!(
+            "\x1b[32mVerified \x1b[34m{}\x1b[32m snapshots, \x1b[31m{}\x1b[32m failed\x1b[0m",
+            results.len(),
+            failed
+        );
+    }
+
+    if command.as_deref() == Some("prune") {
+        let dry_run = flag("--dry-run");
+        match retention::prune(&mut games, prog_settings, &game_conf_path, dry_run) {
+            Ok(reports) => {
+                for report in reports.iter().filter(|report| !report.pruned.is_empty()) {
+                    println!(
+                        "\x1b[34m{}\x1b[0m: {} {} snapshots, {} bytes, keeping {}",
+                        report.game_title,
+                        match dry_run {
+                            true => "would prune",
+                            false => "pruned",
+                        },
+                        report.pruned.len(),
+                        report.freed_bytes,
+                        report.kept.len()
+                    );
+                }
+            }
+            Err(err) => eprintln!("Could not prune due to {}", err),
+        }
+    }
+
+    if let (Some("diff"), Some(title), Some(old)) =
+        (command.as_deref(), positional.first(), positional.get(1))
+    {
+        // NOTE: Without a second snapshot, the live save is compared against the first
+        let diff = search_games(&games, title.to_lowercase()).and_then(|game| {
+            let old = parse_snapshot(game, old)?;
+            let diff = match positional.get(2) {
+                Some(new) => {
+                    let new = parse_snapshot(game, new)?;
+                    game.diff_saves(old, new, prog_settings, flag("--content"))
+                }
+                None => game.diff_live(old, prog_settings, flag("--content")),
+            };
+            Ok(diff)
+        });
+        match diff {
+            Ok(Ok(diff)) if diff.is_empty() => println!("\x1b[32mNo differences\x1b[0m"),
+            Ok(Ok(diff)) => println!("{}", diff),
+            Ok(Err(err)) => eprintln!("Could not compare the snapshots due to {}", err),
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+
+    if let (Some("undo-restore"), Some(title)) = (command.as_deref(), positional.first()) {
+        match search_games_mut(&mut games, title.to_lowercase()) {
+            Ok(game) => match game.undo_restore(prog_settings) {
+                Ok(event) => println!(
+                    "\x1b[32mPut the live save of \x1b[34m{}\x1b[32m back as it was before restoring {}\x1b[0m",
+                    game.game_title, event.restored
+                ),
+                Err(err) => eprintln!("Could not undo the last restore due to {}", err),
+            },
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+
+    if let (Some("relocate"), Some(target)) = (command.as_deref(), positional.first()) {
+        let stored = PathBuf::from(target);
+        // NOTE: Like a local destination, an absolute target is used as given, e.g. a drive mounted elsewhere
+        let absolute = stored.is_absolute();
+        let new_base = match absolute {
+            true => stored.clone(),
+            false => under_home(&home_dir, &stored),
+        };
+        let old_base = prog_settings.save_base_path.clone();
+        let mut show = |progress: &RelocateProgress| {
+            print!(
+                "\r\x1b[36m{}/{}\x1b[0m files, {}/{} bytes",
+                progress.files_done,
+                progress.files_total,
+                progress.bytes_done,
+                progress.bytes_total
+            );
+            let _ = std::io::stdout().flush();
+        };
+        match relocate(
+            &mut games,
+            prog_settings,
+            &game_conf_path,
+            &new_base,
+            false,
+            &mut show,
+        ) {
+            Ok(_) => {
+                // NOTE: oxi.json keeps relative paths relative to home, so write back what was given
+                prog_settings.save_base_path = stored;
+                prog_settings.save_base_absolute = absolute;
+                // NOTE: The old store stays until oxi.json points at the new one
+                if let Err(err) = save_conf(&settings_list, settings_file) {
+                    eprintln!(
+                        "\n\x1b[31mCould not write {:?}, it still points at {:?}: {}\x1b[0m",
+                        settings_file, old_base, err
+                    );
+                    std::process::exit(1);
+                }
+                match relocate::finish(&old_base, &new_base, false) {
+                    Ok(()) => println!(
+                        "\n\x1b[32mMoved the backups to \x1b[34m{:?}\x1b[0m",
+                        new_base
+                    ),
+                    Err(err) => eprintln!(
+                        "\nMoved the backups to {:?}, but could not remove {:?} due to {}",
+                        new_base, old_base, err
+                    ),
+                }
+            }
+            Err(err) => eprintln!("\nCould not move the backups due to {}", err),
+        }
+    }
 
     // After modifications, write the `games` vector back to the configuration file
-    write_conf(games, &game_conf_path);
+    if let Err(err) = save_conf(&games, &game_conf_path) {
+        eprintln!(
+            "\x1b[31mCould not write {:?}: {}\x1b[0m",
+            game_conf_path, err
+        );
+        std::process::exit(1);
+    }
     let discovered_games = discover_games(false);
     // TEST: Shows layout of Game. Here to debug when implementing merger
     write_conf(